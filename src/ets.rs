@@ -1,10 +1,19 @@
 // SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod bpf;
+pub mod cgroup;
+pub mod cooldown;
+pub mod energy_source;
 pub mod etscomponent;
+pub mod impact;
 pub mod iteration_scheduler;
+pub mod packet_capture;
+pub mod pcap;
+pub mod protocol;
 pub mod report;
 pub mod service;
+pub mod storage;
 pub mod system_call;
 pub mod test;
 
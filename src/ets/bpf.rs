@@ -0,0 +1,512 @@
+// SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use ipnet::IpNet;
+use libc::{
+    sock_filter, BPF_ABS, BPF_ALU, BPF_AND, BPF_B, BPF_H, BPF_IND, BPF_JA, BPF_JEQ, BPF_JMP,
+    BPF_JSET, BPF_K, BPF_LD, BPF_LDX, BPF_MSH, BPF_RET, BPF_W,
+};
+use std::error::Error;
+use std::ffi::CString;
+
+use super::packet_capture::{CaptureRule, IPPROTO_TCP, IPPROTO_UDP};
+
+pub const BPF_ACCEPT: u32 = 65_536;
+
+// ancillary data: interface index of the packet
+const SKF_AD_OFF: u32 = -0x1000i32 as u32;
+const SKF_AD_IFINDEX: u32 = 8;
+
+#[derive(Clone, Copy)]
+enum Jump {
+    Next,
+    Label(usize),
+    Accept,
+}
+
+struct Insn {
+    code: u32,
+    k: u32,
+    jt: Jump,
+    jf: Jump,
+}
+
+/// Classic BPF program with forward jumps to labels, resolved by `assemble`.
+struct Program {
+    insns: Vec<Insn>,
+    labels: Vec<Option<usize>>,
+}
+
+impl Program {
+    fn new() -> Self {
+        Self {
+            insns: Vec::new(),
+            labels: Vec::new(),
+        }
+    }
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+    fn bind(&mut self, label: usize) {
+        self.labels[label] = Some(self.insns.len());
+    }
+    fn stmt(&mut self, code: u32, k: u32) {
+        self.jump(code, k, Jump::Next, Jump::Next);
+    }
+    fn jump(&mut self, code: u32, k: u32, jt: Jump, jf: Jump) {
+        self.insns.push(Insn { code, k, jt, jf });
+    }
+    fn goto(&mut self, to: Jump) {
+        self.jump(BPF_JMP | BPF_JA, 0, to, to);
+    }
+
+    // Packets falling through the end are rejected.
+    fn assemble(self) -> Result<Vec<sock_filter>, Box<dyn Error>> {
+        let reject = self.insns.len();
+        let accept = reject + 1;
+        let target = |jump: &Jump, from: usize| -> usize {
+            match jump {
+                Jump::Next => from + 1,
+                Jump::Label(label) => self.labels[*label].unwrap_or(reject),
+                Jump::Accept => accept,
+            }
+        };
+
+        let mut filter = Vec::new();
+        for (i, insn) in self.insns.iter().enumerate() {
+            let (mut jt, mut jf, mut k) = (0, 0, insn.k);
+            if insn.code == BPF_JMP | BPF_JA {
+                k = (target(&insn.jt, i) - i - 1) as u32;
+            } else if insn.code & 0x07 == BPF_JMP {
+                let offset = |to: usize| -> Result<u8, Box<dyn Error>> {
+                    match u8::try_from(to - i - 1) {
+                        Ok(offset) => Ok(offset),
+                        Err(_) => bail!("Capture filter too long (too many hosts or ports)"),
+                    }
+                };
+                jt = offset(target(&insn.jt, i))?;
+                jf = offset(target(&insn.jf, i))?;
+            }
+            filter.push(sock_filter {
+                code: insn.code as u16,
+                jt,
+                jf,
+                k,
+            });
+        }
+        for k in [0, BPF_ACCEPT] {
+            filter.push(sock_filter {
+                code: (BPF_RET | BPF_K) as u16,
+                jt: 0,
+                jf: 0,
+                k,
+            });
+        }
+        Ok(filter)
+    }
+}
+
+// ===
+
+/// Classic BPF equivalent of the capture `rules`, for packets starting at the
+/// network header. A packet is accepted when it matches one of the rules:
+/// `ifindex <interface> and (net <h1> or net <h2>...) and (port <p1> or ...)`.
+pub fn compile_filter(rules: &[CaptureRule]) -> Result<Vec<sock_filter>, Box<dyn Error>> {
+    if rules.iter().all(|r| r.ports.is_empty()) {
+        bail!("No port to capture");
+    }
+
+    let mut prog = Program::new();
+    for rule in rules.iter().filter(|r| !r.ports.is_empty()) {
+        if let Some(filter) = &rule.filter {
+            bail!(
+                "Capture filter \"{}\" needs the TShark transfer backend",
+                filter
+            );
+        }
+
+        let next_rule = prog.label();
+        if let Some(ifindex) = interface_index(&rule.interface)? {
+            prog.stmt(BPF_LD | BPF_W | BPF_ABS, SKF_AD_OFF + SKF_AD_IFINDEX);
+            prog.jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                ifindex,
+                Jump::Next,
+                Jump::Label(next_rule),
+            );
+        }
+
+        let (v4, v6): (Vec<&IpNet>, Vec<&IpNet>) =
+            rule.hosts.iter().partition(|h| matches!(h, IpNet::V4(_)));
+        let any_host = rule.hosts.is_empty();
+
+        prog.stmt(BPF_LD | BPF_B | BPF_ABS, 0);
+        prog.stmt(BPF_ALU | BPF_AND | BPF_K, 0xf0);
+        let ipv6 = prog.label();
+        if any_host || !v4.is_empty() {
+            let jf = if any_host || !v6.is_empty() {
+                Jump::Label(ipv6)
+            } else {
+                Jump::Label(next_rule)
+            };
+            prog.jump(BPF_JMP | BPF_JEQ | BPF_K, 0x40, Jump::Next, jf);
+            compile_ipv4(&mut prog, &v4, &rule.ports, next_rule);
+        }
+        if any_host || !v6.is_empty() {
+            prog.bind(ipv6);
+            prog.jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                0x60,
+                Jump::Next,
+                Jump::Label(next_rule),
+            );
+            compile_ipv6(&mut prog, &v6, &rule.ports, next_rule);
+        }
+
+        prog.bind(next_rule);
+    }
+
+    prog.assemble()
+}
+
+fn interface_index(interface: &Option<String>) -> Result<Option<u32>, Box<dyn Error>> {
+    match interface.as_deref() {
+        None | Some("any") => Ok(None),
+        Some(name) => {
+            let index = unsafe { libc::if_nametoindex(CString::new(name)?.as_ptr()) };
+            if index == 0 {
+                bail!("Unknown capture interface {}", name);
+            }
+            Ok(Some(index))
+        }
+    }
+}
+
+fn compile_ipv4(prog: &mut Program, nets: &[&IpNet], ports: &[u32], next_rule: usize) {
+    let transport = prog.label();
+    for (i, net) in nets.iter().enumerate() {
+        let IpNet::V4(net) = net else { continue };
+        let mask = u32::from(net.netmask());
+        for (j, offset) in [12, 16].into_iter().enumerate() {
+            prog.stmt(BPF_LD | BPF_W | BPF_ABS, offset);
+            if mask != u32::MAX {
+                prog.stmt(BPF_ALU | BPF_AND | BPF_K, mask);
+            }
+            let jf = if i == nets.len() - 1 && j == 1 {
+                Jump::Label(next_rule)
+            } else {
+                Jump::Next
+            };
+            prog.jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                u32::from(net.network()),
+                Jump::Label(transport),
+                jf,
+            );
+        }
+    }
+
+    prog.bind(transport);
+    compile_protocol(prog, 9, next_rule);
+    // first fragment only (ports are unknown in the next ones)
+    prog.stmt(BPF_LD | BPF_H | BPF_ABS, 6);
+    prog.jump(
+        BPF_JMP | BPF_JSET | BPF_K,
+        0x1fff,
+        Jump::Label(next_rule),
+        Jump::Next,
+    );
+    prog.stmt(BPF_LDX | BPF_B | BPF_MSH, 0);
+    compile_ports(prog, BPF_IND, 0, ports, next_rule);
+}
+
+// Extension headers are not followed: only TCP or UDP right after the header.
+fn compile_ipv6(prog: &mut Program, nets: &[&IpNet], ports: &[u32], next_rule: usize) {
+    let transport = prog.label();
+    for (i, net) in nets.iter().enumerate() {
+        let IpNet::V6(net) = net else { continue };
+        let network = net.network().octets();
+        let mask = net.netmask().octets();
+        for (j, offset) in [8, 24].into_iter().enumerate() {
+            let mismatch = if i == nets.len() - 1 && j == 1 {
+                next_rule
+            } else {
+                prog.label()
+            };
+            // 32 bits words of the address covered by the prefix
+            let words: Vec<(u32, u32, u32)> = (0..4)
+                .map(|w| {
+                    let word = |b: &[u8; 16]| {
+                        u32::from_be_bytes([b[4 * w], b[4 * w + 1], b[4 * w + 2], b[4 * w + 3]])
+                    };
+                    (offset + 4 * w as u32, word(&network), word(&mask))
+                })
+                .filter(|(_, _, mask)| *mask != 0)
+                .collect();
+            if words.is_empty() {
+                prog.goto(Jump::Label(transport));
+            }
+            for (w, (word_offset, value, word_mask)) in words.iter().enumerate() {
+                prog.stmt(BPF_LD | BPF_W | BPF_ABS, *word_offset);
+                if *word_mask != u32::MAX {
+                    prog.stmt(BPF_ALU | BPF_AND | BPF_K, *word_mask);
+                }
+                let jt = if w == words.len() - 1 {
+                    Jump::Label(transport)
+                } else {
+                    Jump::Next
+                };
+                prog.jump(BPF_JMP | BPF_JEQ | BPF_K, *value, jt, Jump::Label(mismatch));
+            }
+            if mismatch != next_rule {
+                prog.bind(mismatch);
+            }
+        }
+    }
+
+    prog.bind(transport);
+    compile_protocol(prog, 6, next_rule);
+    compile_ports(prog, BPF_ABS, 40, ports, next_rule);
+}
+
+fn compile_protocol(prog: &mut Program, offset: u32, next_rule: usize) {
+    let ports = prog.label();
+    prog.stmt(BPF_LD | BPF_B | BPF_ABS, offset);
+    prog.jump(
+        BPF_JMP | BPF_JEQ | BPF_K,
+        IPPROTO_TCP,
+        Jump::Label(ports),
+        Jump::Next,
+    );
+    prog.jump(
+        BPF_JMP | BPF_JEQ | BPF_K,
+        IPPROTO_UDP,
+        Jump::Next,
+        Jump::Label(next_rule),
+    );
+    prog.bind(ports);
+}
+
+// Source then destination port, at `offset` of the transport header.
+fn compile_ports(prog: &mut Program, mode: u32, offset: u32, ports: &[u32], next_rule: usize) {
+    for port_offset in [offset, offset + 2] {
+        prog.stmt(BPF_LD | BPF_H | mode, port_offset);
+        for (i, port) in ports.iter().enumerate() {
+            let jf = if port_offset != offset && i == ports.len() - 1 {
+                Jump::Label(next_rule)
+            } else {
+                Jump::Next
+            };
+            prog.jump(BPF_JMP | BPF_JEQ | BPF_K, *port, Jump::Accept, jf);
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::ets::packet_capture::parse_host;
+
+    /// Run `filter` on `packet` the way the kernel does (subset of cBPF used here).
+    pub fn run(filter: &[sock_filter], packet: &[u8], ifindex: u32) -> u32 {
+        let (mut a, mut x, mut pc) = (0u32, 0u32, 0usize);
+        let load = |offset: u32, size: usize| -> Option<u32> {
+            if offset == SKF_AD_OFF + SKF_AD_IFINDEX {
+                return Some(ifindex);
+            }
+            let b = packet.get(offset as usize..offset as usize + size)?;
+            Some(b.iter().fold(0, |v, b| (v << 8) | u32::from(*b)))
+        };
+        loop {
+            let insn = &filter[pc];
+            let code = u32::from(insn.code);
+            pc += 1;
+            let size = match code & 0x18 {
+                BPF_W => 4,
+                BPF_H => 2,
+                _ => 1,
+            };
+            match code & 0x07 {
+                BPF_LD => {
+                    let offset = if code & 0xe0 == BPF_IND {
+                        x + insn.k
+                    } else {
+                        insn.k
+                    };
+                    match load(offset, size) {
+                        Some(v) => a = v,
+                        None => return 0,
+                    }
+                }
+                BPF_LDX => x = 4 * (u32::from(packet[insn.k as usize]) & 0xf),
+                BPF_ALU => a &= insn.k,
+                BPF_JMP if code & 0xf0 == BPF_JA => pc += insn.k as usize,
+                BPF_JMP => {
+                    let cond = if code & 0xf0 == BPF_JSET {
+                        a & insn.k != 0
+                    } else {
+                        a == insn.k
+                    };
+                    pc += usize::from(if cond { insn.jt } else { insn.jf });
+                }
+                _ => return insn.k,
+            }
+        }
+    }
+
+    fn ipv4_packet(src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; 40];
+        packet[0] = 0x45;
+        packet[9] = 6;
+        packet[12..16].copy_from_slice(&src);
+        packet[16..20].copy_from_slice(&dst);
+        packet[20..22].copy_from_slice(&sport.to_be_bytes());
+        packet[22..24].copy_from_slice(&dport.to_be_bytes());
+        packet
+    }
+
+    fn ipv6_packet(src: &str, dst: &str, sport: u16, dport: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; 60];
+        packet[0] = 0x60;
+        packet[6] = 17;
+        let addr = |s: &str| s.parse::<std::net::Ipv6Addr>().unwrap().octets();
+        packet[8..24].copy_from_slice(&addr(src));
+        packet[24..40].copy_from_slice(&addr(dst));
+        packet[40..42].copy_from_slice(&sport.to_be_bytes());
+        packet[42..44].copy_from_slice(&dport.to_be_bytes());
+        packet
+    }
+
+    fn rule(hosts: &[&str], ports: &[u32]) -> CaptureRule {
+        CaptureRule {
+            interface: None,
+            hosts: hosts.iter().map(|h| parse_host(h).unwrap()).collect(),
+            ports: ports.to_vec(),
+            filter: None,
+        }
+    }
+
+    #[test]
+    fn compile_filter_ports() -> Result<(), Box<dyn Error>> {
+        let filter = compile_filter(&[rule(&["127.0.0.1"], &[8080, 4326])])?;
+
+        // header + 2 * (load port + 2 ports) + reject + accept
+        assert_eq!(13 + 6 + 2, filter.len());
+        assert_eq!(0x7f000001, filter[4].k);
+        assert_eq!(0, filter[filter.len() - 2].k);
+        assert_eq!(BPF_ACCEPT, filter[filter.len() - 1].k);
+
+        // last destination port rejects when not matching
+        let last = &filter[filter.len() - 3];
+        assert_eq!(4326, last.k);
+        assert_eq!(1, last.jt);
+        assert_eq!(0, last.jf);
+
+        let lo = [127, 0, 0, 1];
+        assert_eq!(
+            BPF_ACCEPT,
+            run(&filter, &ipv4_packet(lo, lo, 50000, 8080), 1)
+        );
+        assert_eq!(
+            BPF_ACCEPT,
+            run(&filter, &ipv4_packet(lo, lo, 4326, 50000), 1)
+        );
+        assert_eq!(0, run(&filter, &ipv4_packet(lo, lo, 50000, 22), 1));
+        assert_eq!(
+            0,
+            run(
+                &filter,
+                &ipv4_packet([10, 0, 0, 1], [10, 0, 0, 2], 4326, 22),
+                1
+            )
+        );
+        assert_eq!(0, run(&filter, &ipv6_packet("::1", "::1", 50000, 8080), 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn compile_filter_hosts() -> Result<(), Box<dyn Error>> {
+        let filter = compile_filter(&[
+            rule(&["172.17.0.0/16", "fd00::/8"], &[80]),
+            rule(&["::1"], &[5432]),
+            rule(&[], &[6379]),
+        ])?;
+
+        let container = [172, 17, 0, 2];
+        let other = [10, 0, 0, 1];
+        assert_eq!(
+            BPF_ACCEPT,
+            run(&filter, &ipv4_packet(other, container, 1, 80), 1)
+        );
+        assert_eq!(
+            BPF_ACCEPT,
+            run(&filter, &ipv4_packet(container, other, 80, 1), 1)
+        );
+        assert_eq!(0, run(&filter, &ipv4_packet(other, other, 1, 80), 1));
+        assert_eq!(0, run(&filter, &ipv4_packet(container, other, 1, 5432), 1));
+
+        assert_eq!(
+            BPF_ACCEPT,
+            run(&filter, &ipv6_packet("fd12::1", "fe80::1", 1, 80), 1)
+        );
+        assert_eq!(
+            0,
+            run(&filter, &ipv6_packet("fe80::2", "fe80::1", 1, 80), 1)
+        );
+        assert_eq!(
+            BPF_ACCEPT,
+            run(&filter, &ipv6_packet("::1", "::1", 5432, 1), 1)
+        );
+        assert_eq!(0, run(&filter, &ipv6_packet("::2", "::3", 5432, 1), 1));
+
+        // any host, IPv4 or IPv6
+        assert_eq!(
+            BPF_ACCEPT,
+            run(&filter, &ipv4_packet(other, other, 1, 6379), 1)
+        );
+        assert_eq!(
+            BPF_ACCEPT,
+            run(&filter, &ipv6_packet("::2", "::3", 6379, 1), 1)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn compile_filter_interface() -> Result<(), Box<dyn Error>> {
+        let mut r = rule(&["127.0.0.1"], &[8080]);
+        r.interface = Some("lo".into());
+        let filter = compile_filter(&[r])?;
+
+        let lo = [127, 0, 0, 1];
+        let index = interface_index(&Some("lo".into()))?.unwrap();
+        assert_eq!(
+            BPF_ACCEPT,
+            run(&filter, &ipv4_packet(lo, lo, 1, 8080), index)
+        );
+        assert_eq!(0, run(&filter, &ipv4_packet(lo, lo, 1, 8080), index + 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn compile_filter_errors() {
+        assert!(compile_filter(&[]).is_err());
+        assert!(compile_filter(&[rule(&["127.0.0.1"], &[])]).is_err());
+
+        let ports: Vec<u32> = (1..200).collect();
+        assert!(compile_filter(&[rule(&["127.0.0.1"], &ports)]).is_err());
+
+        let mut r = rule(&["127.0.0.1"], &[80]);
+        r.interface = Some("etsdiff-none0".into());
+        assert!(compile_filter(&[r]).is_err());
+
+        let mut r = rule(&["127.0.0.1"], &[80]);
+        r.filter = Some("tcp".into());
+        assert!(compile_filter(&[r]).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+// SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Where `Service::prepare` creates the cgroup (v2) of each `process_name`.
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup/etsdiff.slice";
+
+/// Content of a cgroup interface file.
+pub fn read_file(cgroup: &str, name: &str) -> Result<String, Box<dyn Error>> {
+    let path = Path::new(cgroup).join(name);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(content),
+        Err(err) => bail!("Can't read {}: {}", path.display(), err),
+    }
+}
+
+/// Single value file, like `memory.current`.
+pub fn read_value(cgroup: &str, name: &str) -> Result<u64, Box<dyn Error>> {
+    parse_value(&read_file(cgroup, name)?)
+}
+
+fn parse_value(content: &str) -> Result<u64, Box<dyn Error>> {
+    match content.trim().parse::<u64>() {
+        Ok(value) => Ok(value),
+        Err(_) => bail!("Not a cgroup value: {}", content.trim()),
+    }
+}
+
+/// Flat keyed file, like `memory.stat` or `cpu.stat` ("key value" lines).
+pub fn read_keyed(cgroup: &str, name: &str) -> Result<BTreeMap<String, u64>, Box<dyn Error>> {
+    Ok(parse_keyed(&read_file(cgroup, name)?))
+}
+
+pub fn parse_keyed(content: &str) -> BTreeMap<String, u64> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key.into(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// `io.stat` counters (rbytes, wbytes, rios, wios...) summed over devices.
+pub fn parse_io_stat(content: &str) -> BTreeMap<String, u64> {
+    let mut ret = BTreeMap::new();
+    for line in content.lines() {
+        // "<major>:<minor> rbytes=1024 wbytes=0 rios=1 wios=0 dbytes=0 dios=0"
+        for field in line.split_whitespace().skip(1) {
+            if let Some((key, value)) = field.split_once('=') {
+                if let Ok(value) = value.parse::<u64>() {
+                    *ret.entry(key.into()).or_insert(0) += value;
+                }
+            }
+        }
+    }
+    ret
+}
+
+// ===
+
+/// `memory.peak` reset for the test: since Linux 6.12 writing to the file
+/// resets the peak seen through the same file descriptor. On older kernels the
+/// peak is the one since the cgroup creation (in `Service::prepare`).
+pub struct MemoryPeak {
+    cgroup: String,
+    file: Option<File>,
+}
+
+impl MemoryPeak {
+    pub fn reset(cgroup: &str) -> Self {
+        let path = Path::new(cgroup).join("memory.peak");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .and_then(|mut f| f.write_all(b"reset\n").map(|_| f))
+            .ok();
+        Self {
+            cgroup: cgroup.into(),
+            file,
+        }
+    }
+
+    /// None when the kernel doesn't give the peak (before Linux 5.19).
+    pub fn read(&mut self) -> Option<u64> {
+        if let Some(ref mut f) = self.file {
+            let mut content = String::new();
+            if f.seek(SeekFrom::Start(0)).is_ok() && f.read_to_string(&mut content).is_ok() {
+                if let Ok(peak) = parse_value(&content) {
+                    return Some(peak);
+                }
+            }
+        }
+        read_value(&self.cgroup, "memory.peak").ok()
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cgroup_parse_files() {
+        assert_eq!(4096, parse_value("4096\n").unwrap());
+        assert!(parse_value("max\n").is_err());
+
+        let stat = parse_keyed("anon 1048576\nfile 4096\nfile_mapped 2048\n");
+        assert_eq!(3, stat.len());
+        assert_eq!(1048576, stat["anon"]);
+        assert_eq!(2048, stat["file_mapped"]);
+    }
+
+    #[test]
+    fn cgroup_parse_io_stat() {
+        let stat = parse_io_stat(
+            "8:0 rbytes=4096 wbytes=1024 rios=2 wios=1 dbytes=0 dios=0\n\
+             259:0 rbytes=100 wbytes=0 rios=1 wios=0 dbytes=0 dios=0\n",
+        );
+        assert_eq!(4196, stat["rbytes"]);
+        assert_eq!(1024, stat["wbytes"]);
+        assert_eq!(3, stat["rios"]);
+        assert_eq!(1, stat["wios"]);
+
+        assert!(parse_io_stat("").is_empty());
+    }
+
+    #[test]
+    fn cgroup_read_files() -> Result<(), Box<dyn Error>> {
+        let cgroup = "/tmp/etsdiff_cgroup_read";
+        let _ = std::fs::remove_dir_all(cgroup);
+        std::fs::create_dir_all(cgroup)?;
+        std::fs::write(format!("{cgroup}/memory.current"), "8192\n")?;
+        std::fs::write(
+            format!("{cgroup}/cpu.stat"),
+            "usage_usec 30\nuser_usec 20\n",
+        )?;
+
+        assert_eq!(8192, read_value(cgroup, "memory.current")?);
+        assert_eq!(20, read_keyed(cgroup, "cpu.stat")?["user_usec"]);
+        assert!(read_value(cgroup, "memory.swap.current").is_err());
+        assert!(MemoryPeak::reset(cgroup).read().is_none());
+
+        std::fs::remove_dir_all(cgroup)?;
+
+        Ok(())
+    }
+}
@@ -1,39 +1,101 @@
 // SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use toml::Table;
+use std::error::Error;
+use std::time::Duration;
+use toml::{Table, Value};
 
+use super::cooldown::Cooldown;
+use super::energy_source::{EnergySourceType, VJOULE_DEFAULT_TIMEOUT};
+use super::etscomponent::{Aggregation, CommandComponent};
 use super::etsdiff::ETSdiff;
+use super::impact::ImpactModel;
 use super::iteration_scheduler::SchedulerType;
+use super::packet_capture::{parse_host, CaptureSettings, CaptureType};
 use super::service::Service;
+use super::storage::StorageMode;
 use super::system_call::SystemCall;
 use super::test::SystemCallTest;
 
 pub trait ConfigReader {
-    fn read(config: &str, etsd: &mut ETSdiff);
+    fn read(config: &str, etsd: &mut ETSdiff) -> Result<(), Box<dyn Error>>;
 }
 
 // ===
 pub struct TOMLConfigReader<'a> {
     toml: &'a Table,
     etsd: &'a mut ETSdiff,
+    // filters are only supported by the TShark backend
+    capture_type: CaptureType,
 }
 
 impl<'a> ConfigReader for TOMLConfigReader<'a> {
-    fn read(config: &str, etsd: &mut ETSdiff) {
+    fn read(config: &str, etsd: &mut ETSdiff) -> Result<(), Box<dyn Error>> {
         let mut cr = TOMLConfigReader {
-            toml: &config.parse::<Table>().unwrap(),
+            toml: &config.parse::<Table>()?,
             etsd,
+            capture_type: CaptureType::AfPacket,
         };
 
         if let Some(table) = cr.toml["Scheduler"].as_table() {
-            cr.read_scheduler(table);
+            cr.read_scheduler(table)?;
+        }
+
+        if cr.toml.contains_key("Energy") {
+            if let Some(table) = cr.toml["Energy"].as_table() {
+                cr.read_energy(table)?;
+            }
+        }
+
+        if cr.toml.contains_key("Transfer") {
+            if let Some(table) = cr.toml["Transfer"].as_table() {
+                cr.read_transfer(table)?;
+            }
+        }
+
+        if cr.toml.contains_key("Storage") {
+            if let Some(table) = cr.toml["Storage"].as_table() {
+                cr.read_storage(table)?;
+            }
+        }
+
+        if cr.toml.contains_key("Metrics") {
+            if let Some(table) = cr.toml["Metrics"].as_table() {
+                for name in table.keys() {
+                    cr.read_metric(name, table[name].as_table().unwrap())?;
+                }
+            }
+        }
+
+        // after the components settings, to turn some off
+        if cr.toml.contains_key("Components") {
+            if let Some(table) = cr.toml["Components"].as_table() {
+                cr.read_components(table)?;
+            }
+        }
+
+        if cr.toml.contains_key("Impact") {
+            if let Some(table) = cr.toml["Impact"].as_table() {
+                cr.read_impact(table)?;
+            }
+        }
+
+        if cr.toml.contains_key("Cooldown") {
+            if let Some(table) = cr.toml["Cooldown"].as_table() {
+                cr.read_cooldown(table);
+            }
+        }
+
+        if cr.toml.contains_key("Baseline") {
+            if let Some(table) = cr.toml["Baseline"].as_table() {
+                cr.read_baseline(table)?;
+            }
         }
 
         if cr.toml.contains_key("Services") {
             if let Some(table) = cr.toml["Services"].as_table() {
                 for name in table.keys() {
-                    cr.read_service(name, table[name].as_table().unwrap());
+                    cr.read_service(name, table[name].as_table().unwrap())?;
                 }
             }
         }
@@ -45,22 +107,270 @@ impl<'a> ConfigReader for TOMLConfigReader<'a> {
                 }
             }
         }
+        Ok(())
     }
 }
 
 impl<'a> TOMLConfigReader<'a> {
-    fn read_scheduler(&mut self, toml_scheduler: &Table) {
-        if let Some(nb_iteration) = toml_scheduler["nb_iteration"].as_integer() {
-            if let Some(stype) = toml_scheduler["type"].as_str() {
-                if stype == "StageredScheduler" {
-                    self.etsd
-                        .set_scheduler(SchedulerType::StageredScheduler, nb_iteration as u32);
+    fn read_scheduler(&mut self, toml_scheduler: &Table) -> Result<(), Box<dyn Error>> {
+        if toml_scheduler.contains_key("type") || toml_scheduler.contains_key("nb_iteration") {
+            let Some(stype) = toml_scheduler.get("type").and_then(|t| t.as_str()) else {
+                bail!("Scheduler needs a type");
+            };
+            let st = match stype {
+                "StageredScheduler" => SchedulerType::StageredScheduler,
+                "BlockedScheduler" => SchedulerType::BlockedScheduler,
+                "LatinSquareScheduler" => SchedulerType::LatinSquareScheduler,
+                "RandomizedScheduler" => {
+                    let seed = if toml_scheduler.contains_key("seed") {
+                        Some(toml_scheduler["seed"].as_integer().unwrap() as u64)
+                    } else {
+                        None
+                    };
+                    SchedulerType::RandomizedScheduler(seed)
+                }
+                "AdaptiveScheduler" => {
+                    if !toml_scheduler.contains_key("target_width") {
+                        bail!("AdaptiveScheduler needs a target_width");
+                    }
+                    let min_iteration = if toml_scheduler.contains_key("min_iteration") {
+                        toml_scheduler["min_iteration"].as_integer().unwrap() as u32
+                    } else {
+                        3
+                    };
+                    let time_budget = if toml_scheduler.contains_key("time_budget") {
+                        Some(Duration::from_secs(
+                            toml_scheduler["time_budget"].as_integer().unwrap() as u64,
+                        ))
+                    } else {
+                        None
+                    };
+                    SchedulerType::AdaptiveScheduler {
+                        min_iteration,
+                        target_width: Self::read_number(&toml_scheduler["target_width"]),
+                        time_budget,
+                    }
                 }
+                stype => bail!("Unknown scheduler type {}", stype),
+            };
+            let nb_iteration = match toml_scheduler
+                .get("nb_iteration")
+                .and_then(|n| n.as_integer())
+                .and_then(|n| u32::try_from(n).ok())
+            {
+                Some(nb_iteration) if nb_iteration > 0 => nb_iteration,
+                _ => bail!("Scheduler nb_iteration must be a positive integer"),
+            };
+            self.etsd.set_scheduler(st, nb_iteration);
+        }
+        if toml_scheduler.contains_key("warmup") {
+            self.etsd
+                .set_warmup(toml_scheduler["warmup"].as_integer().unwrap() as u32);
+        }
+        Ok(())
+    }
+
+    fn read_energy(&mut self, toml_energy: &Table) -> Result<(), Box<dyn Error>> {
+        let mut timeout = VJOULE_DEFAULT_TIMEOUT;
+        if toml_energy.contains_key("timeout") {
+            timeout = Duration::from_secs(toml_energy["timeout"].as_integer().unwrap() as u64);
+        }
+
+        if toml_energy.contains_key("type") {
+            match toml_energy["type"].as_str().unwrap() {
+                "Vjoule" => self.etsd.set_e_component(EnergySourceType::Vjoule(timeout)),
+                "RAPL" => self.etsd.set_e_component(EnergySourceType::Rapl),
+                "Replay" => match toml_energy.get("path").and_then(|path| path.as_str()) {
+                    Some(path) => self
+                        .etsd
+                        .set_e_component(EnergySourceType::Replay(path.into())),
+                    None => bail!("Replay energy type needs a path"),
+                },
+                etype => bail!("Unknown energy type {}", etype),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_capture_settings(
+        &self,
+        toml: &Table,
+        settings: &mut CaptureSettings,
+    ) -> Result<(), Box<dyn Error>> {
+        if toml.contains_key("interface") {
+            settings.interface = Some(toml["interface"].as_str().unwrap().into());
+        }
+
+        if toml.contains_key("hosts") {
+            settings.hosts = Vec::new();
+            for host in toml["hosts"].as_array().unwrap() {
+                settings.hosts.push(parse_host(host.as_str().unwrap())?);
+            }
+        }
+
+        if toml.contains_key("filter") {
+            let filter = toml["filter"].as_str().unwrap();
+            if self.capture_type != CaptureType::TShark {
+                bail!(
+                    "Capture filter \"{}\" needs the TShark transfer type",
+                    filter
+                );
+            }
+            settings.filter = Some(filter.into());
+        }
+        Ok(())
+    }
+
+    fn read_transfer(&mut self, toml_transfer: &Table) -> Result<(), Box<dyn Error>> {
+        let mut ct = CaptureType::AfPacket;
+        if toml_transfer.contains_key("type") {
+            match toml_transfer["type"].as_str().unwrap() {
+                "AfPacket" => ct = CaptureType::AfPacket,
+                "TShark" => ct = CaptureType::TShark,
+                "Pcap" => match toml_transfer.get("path").and_then(|path| path.as_str()) {
+                    Some(path) => ct = CaptureType::Pcap(path.into()),
+                    None => bail!("Pcap transfer type needs a path"),
+                },
+                ctype => bail!("Unknown transfer type {}", ctype),
+            }
+        }
+
+        self.capture_type = ct.clone();
+        let mut settings = CaptureSettings::localhost();
+        self.read_capture_settings(toml_transfer, &mut settings)?;
+        self.etsd.set_t_component(ct, settings);
+        Ok(())
+    }
+
+    fn read_components(&mut self, toml_components: &Table) -> Result<(), Box<dyn Error>> {
+        if toml_components.contains_key("enabled") {
+            let names: Vec<String> = toml_components["enabled"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|name| name.as_str().unwrap().into())
+                .collect();
+            self.etsd.enable_components(&names)?;
+        }
+        Ok(())
+    }
+
+    fn read_metric(&mut self, name: &str, toml_metric: &Table) -> Result<(), Box<dyn Error>> {
+        let aggregation = if toml_metric.contains_key("aggregation") {
+            match toml_metric["aggregation"].as_str().unwrap() {
+                "delta" => Aggregation::Delta,
+                "last" => Aggregation::Last,
+                "sum" => Aggregation::Sum,
+                aggregation => bail!("Unknown aggregation {} of metric {}", aggregation, name),
+            }
+        } else {
+            Aggregation::Delta
+        };
+        if !toml_metric.contains_key("after_test") {
+            bail!("Missing after_test command of metric {}", name);
+        }
+        let before = if toml_metric.contains_key("before_test") {
+            toml_metric["before_test"].as_str()
+        } else {
+            None
+        };
+        let mut cc = CommandComponent::new(
+            name,
+            before,
+            toml_metric["after_test"].as_str().unwrap(),
+            aggregation,
+        );
+        if toml_metric.contains_key("unit") {
+            cc.set_unit(toml_metric["unit"].as_str().unwrap());
+        }
+        self.etsd.set_component(Box::new(cc));
+        Ok(())
+    }
+
+    fn read_storage(&mut self, toml_storage: &Table) -> Result<(), Box<dyn Error>> {
+        if toml_storage.contains_key("mode") {
+            match toml_storage["mode"].as_str().unwrap() {
+                "apparent" => self.etsd.set_s_component(StorageMode::Apparent),
+                "allocated" => self.etsd.set_s_component(StorageMode::Allocated),
+                mode => bail!("Unknown storage mode {}", mode),
             }
         }
+        Ok(())
+    }
+
+    // integer or float
+    fn read_number(value: &Value) -> f64 {
+        value
+            .as_float()
+            .unwrap_or_else(|| value.as_integer().unwrap() as f64)
     }
 
-    fn read_service(&mut self, name: &str, toml_service: &Table) {
+    // integer or float `key` of the `section` table
+    fn read_key_number(toml: &Table, section: &str, key: &str) -> Result<f64, Box<dyn Error>> {
+        match toml.get(key) {
+            Some(Value::Float(value)) => Ok(*value),
+            Some(Value::Integer(value)) => Ok(*value as f64),
+            Some(_) => bail!("{} {} must be a number", section, key),
+            None => bail!("Missing {} {}", section, key),
+        }
+    }
+
+    fn read_impact(&mut self, toml_impact: &Table) -> Result<(), Box<dyn Error>> {
+        let factor = |key: &str| Self::read_key_number(toml_impact, "Impact", key);
+        let mut model = ImpactModel::new(
+            factor("carbon_intensity")?,
+            factor("network_kwh_per_gb")?,
+            factor("storage_kwh_per_gb_year")?,
+        );
+        if toml_impact.contains_key("retention_days") {
+            model.retention_days = factor("retention_days")?;
+        }
+        if toml_impact.contains_key("primary_energy_factor") {
+            model.primary_energy_factor = factor("primary_energy_factor")?;
+        }
+        self.etsd.report.set_impact_model(model);
+        Ok(())
+    }
+
+    fn read_cooldown(&mut self, toml_cooldown: &Table) {
+        let mut cooldown = Cooldown::new();
+        let seconds = |key: &str| Duration::from_secs_f64(Self::read_number(&toml_cooldown[key]));
+        if toml_cooldown.contains_key("sleep") {
+            cooldown.sleep = seconds("sleep");
+        }
+        if toml_cooldown.contains_key("timeout") {
+            cooldown.timeout = seconds("timeout");
+        }
+        if toml_cooldown.contains_key("interval") {
+            cooldown.interval = seconds("interval");
+        }
+        if toml_cooldown.contains_key("max_cpu_load") {
+            cooldown.max_cpu_load = Some(Self::read_number(&toml_cooldown["max_cpu_load"]));
+        }
+        if toml_cooldown.contains_key("max_temperature") {
+            cooldown.max_temperature = Some(Self::read_number(&toml_cooldown["max_temperature"]));
+        }
+        if toml_cooldown.contains_key("max_power") {
+            cooldown.max_power = Some(Self::read_number(&toml_cooldown["max_power"]));
+        }
+        self.etsd.set_cooldown(cooldown);
+    }
+
+    fn read_baseline(&mut self, toml_baseline: &Table) -> Result<(), Box<dyn Error>> {
+        if !toml_baseline.contains_key("duration") {
+            bail!("Missing Baseline duration");
+        }
+        match toml_baseline["duration"]
+            .as_integer()
+            .and_then(|duration| u64::try_from(duration).ok())
+        {
+            Some(duration) => self.etsd.set_baseline(Duration::from_secs(duration)),
+            None => bail!("Baseline duration must be a number of seconds"),
+        }
+        Ok(())
+    }
+
+    fn read_service(&mut self, name: &str, toml_service: &Table) -> Result<(), Box<dyn Error>> {
         let mut s = Service::new(name);
 
         if toml_service.contains_key("process_name") {
@@ -87,14 +397,35 @@ impl<'a> TOMLConfigReader<'a> {
             s.release = Some(SystemCall::new(toml_service["release"].as_str().unwrap()));
         }
 
+        self.read_capture_settings(toml_service, &mut s.capture)?;
+
         if toml_service.contains_key("storage_paths") {
             for path in toml_service["storage_paths"].as_array().unwrap() {
                 s.add_storage_path(path.as_str().unwrap());
             }
         }
 
+        if toml_service.contains_key("storage_include") {
+            for pattern in toml_service["storage_include"].as_array().unwrap() {
+                s.add_storage_include(pattern.as_str().unwrap())?;
+            }
+        }
+
+        if toml_service.contains_key("storage_exclude") {
+            for pattern in toml_service["storage_exclude"].as_array().unwrap() {
+                s.add_storage_exclude(pattern.as_str().unwrap())?;
+            }
+        }
+
+        if toml_service.contains_key("storage_probes") {
+            for probe in toml_service["storage_probes"].as_array().unwrap() {
+                s.add_storage_probe(probe.as_str().unwrap());
+            }
+        }
+
         let mut services = self.etsd.services.borrow_mut();
         services.push(s);
+        Ok(())
     }
 
     fn read_test(&mut self, name: &str, toml_test: &Table) {
@@ -106,6 +437,16 @@ impl<'a> TOMLConfigReader<'a> {
                     test.add_service_name(sn.as_str().unwrap());
                 }
             }
+
+            if toml_test.contains_key("functional_units") {
+                test.set_functional_units(
+                    toml_test["functional_units"].as_integer().unwrap() as u64
+                );
+            }
+
+            if toml_test.contains_key("warmup") {
+                test.set_warmup(toml_test["warmup"].as_integer().unwrap() as u32);
+            }
             self.etsd.tests.push(Box::new(test));
         }
 
@@ -119,12 +460,54 @@ impl<'a> TOMLConfigReader<'a> {
 mod tests {
     use super::*;
 
+    use std::any::Any;
+
+    use super::super::etscomponent::{EComponent, SComponent, TComponent};
     use super::super::etsdiff::ETSdiff;
 
     static TOML_TEST: &str = r#"
 [Scheduler]
 type = "StageredScheduler"
 nb_iteration = 5
+warmup = 1
+
+[Energy]
+type = "RAPL"
+
+[Transfer]
+type = "TShark"
+hosts = [ "127.0.0.1", "172.17.0.0/16" ]
+
+[Storage]
+mode = "allocated"
+
+[Metrics."requests"]
+before_test = "cat /tmp/requests"
+after_test = "cat /tmp/requests"
+unit = "requests"
+
+[Metrics."temperature"]
+after_test = "cat /sys/class/thermal/thermal_zone0/temp"
+aggregation = "last"
+
+[Components]
+enabled = [ "energy", "storage", "transfer", "requests" ]
+
+[Impact]
+carbon_intensity = 56
+network_kwh_per_gb = 0.06
+storage_kwh_per_gb_year = 0.0015
+retention_days = 30
+
+[Cooldown]
+sleep = 5
+timeout = 120
+max_cpu_load = 0.1
+max_temperature = 50
+max_power = 20.5
+
+[Baseline]
+duration = 15
 
     
 [Services]
@@ -134,10 +517,15 @@ ports = [ 8_080, 4_326 ]
 prepare = "ls -a -l"
 release = "ls"
 storage_paths = [ "/tmp/s1/queue", "/tmp/s1/session" ]
+storage_probes = [ "du -sb /tmp/s1/db" ]
+storage_exclude = [ "*.lock", "*.tmp" ]
 
 [Services."Service 2"]
 process_name = "pns2"
 clean = "ls -a"
+interface = "lo"
+hosts = [ "::1" ]
+filter = "tcp"
 
 [Services."Service 3"]
 process_name = "pns3"
@@ -150,18 +538,20 @@ clean = "ls -l"
 type = "SystemCall"
 services_names = [ "Service 1", "Service 2" ]
 command_line = "/bin/ls -a -l"
+functional_units = 100
 
 [Tests."Test 2"]
 type = "SystemCall"
 services_names = [ "Service 3" ]
 command_line = "ls"
+warmup = 0
     "#;
 
     #[test]
     fn toml_config_reader_services() {
         let mut etsd = ETSdiff::new();
 
-        TOMLConfigReader::read(TOML_TEST, &mut etsd);
+        TOMLConfigReader::read(TOML_TEST, &mut etsd).unwrap();
 
         let services = etsd.services.borrow();
         assert_eq!(3, services.len());
@@ -189,6 +579,9 @@ command_line = "ls"
         assert_eq!(0, release.arguments().len());
 
         assert_eq!(2, s1.storage_paths.len());
+        assert_eq!(1, s1.storage_probes.len());
+        assert!(s1.storage_filter.include.is_empty());
+        assert_eq!(2, s1.storage_filter.exclude.len());
         assert_eq!("/tmp/s1/queue", s1.storage_paths[0]);
         assert_eq!("/tmp/s1/session", s1.storage_paths[1]);
 
@@ -231,50 +624,444 @@ command_line = "ls"
         assert!(s3.release.is_none());
 
         assert_eq!(0, s3.storage_paths.len());
+
+        for key in ["storage_include", "storage_exclude"] {
+            let mut etsd = ETSdiff::new();
+            let result = TOMLConfigReader::read(
+                &format!("[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Services.\"Web\"]\n{key} = [ \"data/[a\" ]\n"),
+                &mut etsd,
+            );
+            assert!(result
+                .unwrap_err()
+                .to_string()
+                .starts_with("Invalid storage pattern data/[a"));
+        }
     }
 
     #[test]
     fn toml_config_reader_tests() {
         let mut etsd = ETSdiff::new();
 
-        TOMLConfigReader::read(TOML_TEST, &mut etsd);
+        TOMLConfigReader::read(TOML_TEST, &mut etsd).unwrap();
 
         assert_eq!(2, etsd.tests.len());
 
         // 1st test
         assert_eq!("Test 1", etsd.tests[0].name());
         assert_eq!(2, etsd.tests[0].services_names().len());
-        let mut i = 0;
-        for service_name in etsd.tests[0].services_names().iter() {
+        for (i, service_name) in etsd.tests[0].services_names().iter().enumerate() {
             match i {
                 0 => assert_eq!(service_name, "Service 1"),
                 1 => assert_eq!(service_name, "Service 2"),
                 _ => panic!("Unexpected service name"),
             }
-            i += 1;
         }
-        assert!(!&etsd.tests[0].run().is_err());
+        assert!(etsd.tests[0].run().is_ok());
+        assert_eq!(Some(100), etsd.tests[0].functional_units());
 
         // 2nd test
         assert_eq!("Test 2", etsd.tests[1].name());
         assert_eq!(1, etsd.tests[1].services_names().len());
-        let mut i = 0;
-        for service_name in etsd.tests[1].services_names().iter() {
+        for (i, service_name) in etsd.tests[1].services_names().iter().enumerate() {
             match i {
                 0 => assert_eq!(service_name, "Service 3"),
                 _ => panic!("Unexpected service name"),
             }
-            i += 1;
         }
-        assert!(!&etsd.tests[1].run().is_err());
+        assert!(etsd.tests[1].run().is_ok());
+        assert!(etsd.tests[1].functional_units().is_none());
     }
 
     #[test]
     fn toml_config_reader_iteration_scheduler() {
         let mut etsd = ETSdiff::new();
 
-        TOMLConfigReader::read(TOML_TEST, &mut etsd);
+        TOMLConfigReader::read(TOML_TEST, &mut etsd).unwrap();
 
         assert_eq!(5, etsd.scheduler.unwrap().nb_iteration());
+        assert_eq!(1, etsd.warmup);
+        assert!(etsd.tests[0].warmup().is_none());
+        assert_eq!(Some(0), etsd.tests[1].warmup());
+
+        let mut etsd = ETSdiff::new();
+
+        TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"RandomizedScheduler\"\nnb_iteration = 3\nseed = 42\n",
+            &mut etsd,
+        )
+        .unwrap();
+
+        let scheduler = etsd.scheduler.unwrap();
+        assert_eq!(3, scheduler.nb_iteration());
+        assert_eq!(Some(42), scheduler.seed());
+
+        let mut etsd = ETSdiff::new();
+
+        TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"AdaptiveScheduler\"\nnb_iteration = 30\ntarget_width = 0.05\ntime_budget = 3600\n",
+            &mut etsd,
+        ).unwrap();
+
+        let scheduler = etsd.scheduler.unwrap();
+        assert_eq!(30, scheduler.nb_iteration());
+        assert!(scheduler.seed().is_none());
+
+        for stype in ["BlockedScheduler", "LatinSquareScheduler"] {
+            let mut etsd = ETSdiff::new();
+            TOMLConfigReader::read(
+                &format!("[Scheduler]\ntype = \"{stype}\"\nnb_iteration = 4\n"),
+                &mut etsd,
+            )
+            .unwrap();
+            assert_eq!(4, etsd.scheduler.unwrap().nb_iteration());
+        }
+
+        let mut etsd = ETSdiff::new();
+        let result = TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"AdaptiveScheduler\"\nnb_iteration = 30\n",
+            &mut etsd,
+        );
+        assert_eq!(
+            "AdaptiveScheduler needs a target_width",
+            result.unwrap_err().to_string()
+        );
+
+        let mut etsd = ETSdiff::new();
+        let result = TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StaggeredScheduler\"\nnb_iteration = 4\n",
+            &mut etsd,
+        );
+        assert_eq!(
+            "Unknown scheduler type StaggeredScheduler",
+            result.unwrap_err().to_string()
+        );
+
+        for (scheduler, error) in [
+            (
+                "type = \"StaggeredScheduler\"\n",
+                "Unknown scheduler type StaggeredScheduler",
+            ),
+            ("nb_iteration = 4\n", "Scheduler needs a type"),
+            (
+                "type = \"StageredScheduler\"\n",
+                "Scheduler nb_iteration must be a positive integer",
+            ),
+            (
+                "type = \"StageredScheduler\"\nnb_iteration = -1\n",
+                "Scheduler nb_iteration must be a positive integer",
+            ),
+            (
+                "type = \"StageredScheduler\"\nnb_iteration = \"4\"\n",
+                "Scheduler nb_iteration must be a positive integer",
+            ),
+        ] {
+            let mut etsd = ETSdiff::new();
+            let result = TOMLConfigReader::read(&format!("[Scheduler]\n{scheduler}"), &mut etsd);
+            assert_eq!(error, result.unwrap_err().to_string());
+        }
+    }
+
+    #[test]
+    fn toml_config_reader_baseline() {
+        let mut etsd = ETSdiff::new();
+
+        assert!(etsd.baseline_duration.is_none());
+
+        TOMLConfigReader::read(TOML_TEST, &mut etsd).unwrap();
+
+        assert_eq!(Some(Duration::from_secs(15)), etsd.baseline_duration);
+
+        for (baseline, error) in [
+            ("", "Missing Baseline duration"),
+            (
+                "duration = \"15\"\n",
+                "Baseline duration must be a number of seconds",
+            ),
+            (
+                "duration = -15\n",
+                "Baseline duration must be a number of seconds",
+            ),
+        ] {
+            let mut etsd = ETSdiff::new();
+            let result = TOMLConfigReader::read(
+                &format!("[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Baseline]\n{baseline}"),
+                &mut etsd,
+            );
+            assert_eq!(error, result.unwrap_err().to_string());
+        }
+    }
+
+    #[test]
+    fn toml_config_reader_cooldown() {
+        let mut etsd = ETSdiff::new();
+
+        assert!(etsd.cooldown.is_none());
+
+        TOMLConfigReader::read(TOML_TEST, &mut etsd).unwrap();
+
+        let cooldown = etsd.cooldown.unwrap();
+        assert_eq!(Duration::from_secs(5), cooldown.sleep);
+        assert_eq!(Duration::from_secs(120), cooldown.timeout);
+        assert_eq!(Duration::from_secs(1), cooldown.interval);
+        assert_eq!(Some(0.1), cooldown.max_cpu_load);
+        assert_eq!(Some(50.0), cooldown.max_temperature);
+        assert_eq!(Some(20.5), cooldown.max_power);
+    }
+
+    fn capture_type(etsd: &ETSdiff) -> CaptureType {
+        let component: &dyn Any = etsd.component("transfer").unwrap();
+        component
+            .downcast_ref::<TComponent>()
+            .unwrap()
+            .capture_type()
+    }
+
+    #[test]
+    fn toml_config_reader_transfer() {
+        let mut etsd = ETSdiff::new();
+
+        TOMLConfigReader::read(TOML_TEST, &mut etsd).unwrap();
+
+        assert_eq!(CaptureType::TShark, capture_type(&etsd));
+
+        let services = etsd.services.borrow();
+        assert_eq!(CaptureSettings::default(), services[0].capture);
+        let capture = &services[1].capture;
+        assert_eq!(Some("lo"), capture.interface.as_deref());
+        assert_eq!(vec![parse_host("::1").unwrap()], capture.hosts);
+        assert_eq!(Some("tcp"), capture.filter.as_deref());
+
+        let mut etsd = ETSdiff::new();
+        assert_eq!(CaptureType::AfPacket, capture_type(&etsd));
+        TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Transfer]\ntype = \"Pcap\"\npath = \"capture.pcap\"\n",
+            &mut etsd,
+        )
+        .unwrap();
+        assert_eq!(
+            CaptureType::Pcap("capture.pcap".into()),
+            capture_type(&etsd)
+        );
+
+        let mut etsd = ETSdiff::new();
+        let result = TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Transfer]\ntype = \"Tshark\"\n",
+            &mut etsd,
+        );
+        assert_eq!(
+            "Unknown transfer type Tshark",
+            result.unwrap_err().to_string()
+        );
+
+        let mut etsd = ETSdiff::new();
+        let result = TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Transfer]\ntype = \"Pcap\"\n",
+            &mut etsd,
+        );
+        assert_eq!(
+            "Pcap transfer type needs a path",
+            result.unwrap_err().to_string()
+        );
+
+        for config in [
+            "[Transfer]\nfilter = \"tcp\"\n",
+            "[Transfer]\ntype = \"Pcap\"\npath = \"capture.pcap\"\n[Services.\"Web\"]\nfilter = \"tcp\"\n",
+        ] {
+            let mut etsd = ETSdiff::new();
+            let result = TOMLConfigReader::read(
+                &format!("[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n{config}"),
+                &mut etsd,
+            );
+            assert_eq!(
+                "Capture filter \"tcp\" needs the TShark transfer type",
+                result.unwrap_err().to_string()
+            );
+        }
+
+        for config in [
+            "[Transfer]\nhosts = [ \"localhost\" ]\n",
+            "[Services.\"Web\"]\nhosts = [ \"10.0.0.0/33\" ]\n",
+        ] {
+            let mut etsd = ETSdiff::new();
+            let result = TOMLConfigReader::read(
+                &format!("[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n{config}"),
+                &mut etsd,
+            );
+            assert!(result
+                .unwrap_err()
+                .to_string()
+                .starts_with("Invalid capture host"));
+        }
+    }
+
+    fn storage_mode(etsd: &ETSdiff) -> StorageMode {
+        let component: &dyn Any = etsd.component("storage").unwrap();
+        component.downcast_ref::<SComponent>().unwrap().mode()
+    }
+
+    #[test]
+    fn toml_config_reader_storage() {
+        let mut etsd = ETSdiff::new();
+
+        TOMLConfigReader::read(TOML_TEST, &mut etsd).unwrap();
+
+        assert_eq!(StorageMode::Allocated, storage_mode(&etsd));
+
+        let mut etsd = ETSdiff::new();
+        assert_eq!(StorageMode::Apparent, storage_mode(&etsd));
+        TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Storage]\nmode = \"apparent\"\n",
+            &mut etsd,
+        )
+        .unwrap();
+        assert_eq!(StorageMode::Apparent, storage_mode(&etsd));
+
+        let mut etsd = ETSdiff::new();
+        let result = TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Storage]\nmode = \"Allocated\"\n",
+            &mut etsd,
+        );
+        assert_eq!(
+            "Unknown storage mode Allocated",
+            result.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn toml_config_reader_impact() {
+        let mut etsd = ETSdiff::new();
+
+        assert!(etsd.report.impact_model().is_none());
+
+        TOMLConfigReader::read(TOML_TEST, &mut etsd).unwrap();
+
+        let mut expected = ImpactModel::new(56.0, 0.06, 0.0015);
+        expected.retention_days = 30.0;
+        assert_eq!(Some(&expected), etsd.report.impact_model());
+
+        for (impact, error) in [
+            (
+                "network_kwh_per_gb = 0.06\nstorage_kwh_per_gb_year = 0.0015\n",
+                "Missing Impact carbon_intensity",
+            ),
+            (
+                "carbon_intensity = 56\nnetwork_kwh_per_gb = \"0.06\"\nstorage_kwh_per_gb_year = 0.0015\n",
+                "Impact network_kwh_per_gb must be a number",
+            ),
+            (
+                "carbon_intensity = 56\nnetwork_kwh_per_gb = 0.06\nstorage_kwh_per_gb_year = 0.0015\nretention_days = \"30\"\n",
+                "Impact retention_days must be a number",
+            ),
+        ] {
+            let mut etsd = ETSdiff::new();
+            let result = TOMLConfigReader::read(
+                &format!("[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Impact]\n{impact}"),
+                &mut etsd,
+            );
+            assert_eq!(error, result.unwrap_err().to_string());
+        }
+    }
+
+    fn energy_source(etsd: &ETSdiff) -> EnergySourceType {
+        let component: &dyn Any = etsd.component("energy").unwrap();
+        component
+            .downcast_ref::<EComponent>()
+            .unwrap()
+            .source_type()
+    }
+
+    #[test]
+    fn toml_config_reader_energy() {
+        let mut etsd = ETSdiff::new();
+
+        TOMLConfigReader::read(TOML_TEST, &mut etsd).unwrap();
+
+        assert_eq!(EnergySourceType::Rapl, energy_source(&etsd));
+
+        let mut etsd = ETSdiff::new();
+        assert_eq!(
+            EnergySourceType::Vjoule(VJOULE_DEFAULT_TIMEOUT),
+            energy_source(&etsd)
+        );
+        TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Energy]\ntype = \"Vjoule\"\ntimeout = 5\n",
+            &mut etsd,
+        )
+        .unwrap();
+        assert_eq!(
+            EnergySourceType::Vjoule(Duration::from_secs(5)),
+            energy_source(&etsd)
+        );
+
+        let mut etsd = ETSdiff::new();
+        TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Energy]\ntype = \"Replay\"\npath = \"records\"\n",
+            &mut etsd,
+        )
+        .unwrap();
+        assert_eq!(
+            EnergySourceType::Replay("records".into()),
+            energy_source(&etsd)
+        );
+
+        let mut etsd = ETSdiff::new();
+        let result = TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Energy]\ntype = \"Rapl\"\n",
+            &mut etsd,
+        );
+        assert_eq!("Unknown energy type Rapl", result.unwrap_err().to_string());
+
+        let mut etsd = ETSdiff::new();
+        let result = TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Energy]\ntype = \"Replay\"\n",
+            &mut etsd,
+        );
+        assert_eq!(
+            "Replay energy type needs a path",
+            result.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn toml_config_reader_components() {
+        let mut etsd = ETSdiff::new();
+
+        TOMLConfigReader::read(TOML_TEST, &mut etsd).unwrap();
+
+        assert_eq!(
+            vec!["transfer", "storage", "requests", "energy"],
+            etsd.component_names()
+        );
+        assert_eq!(Some("requests"), etsd.component("requests").unwrap().unit());
+
+        let mut etsd = ETSdiff::new();
+
+        TOMLConfigReader::read(
+            "[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Components]\nenabled = [ \"storage\" ]\n",
+            &mut etsd,
+        ).unwrap();
+
+        assert_eq!(vec!["storage"], etsd.component_names());
+    }
+
+    #[test]
+    fn toml_config_reader_metrics() {
+        for (metric, error) in [
+            (
+                "after_test = \"cat /tmp/requests\"\naggregation = \"max\"\n",
+                "Unknown aggregation max of metric requests",
+            ),
+            (
+                "before_test = \"cat /tmp/requests\"\n",
+                "Missing after_test command of metric requests",
+            ),
+        ] {
+            let mut etsd = ETSdiff::new();
+            let result = TOMLConfigReader::read(
+                &format!("[Scheduler]\ntype = \"StageredScheduler\"\nnb_iteration = 2\n[Metrics.\"requests\"]\n{metric}"),
+                &mut etsd,
+            );
+            assert_eq!(error, result.unwrap_err().to_string());
+        }
     }
 }
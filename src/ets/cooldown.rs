@@ -0,0 +1,209 @@
+// SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::energy_source::{rapl_delta, read_rapl_zones, read_system_cpu_usage, read_u64};
+use super::report::Conditions;
+
+/// Wait before a test, its services prepared: a fixed sleep, then samples
+/// until the CPU load, package temperature and package power are under their
+/// thresholds or the timeout is reached. A threshold on a value the system
+/// doesn't give is ignored.
+pub struct Cooldown {
+    pub sleep: Duration,
+    /// Busy part of the CPU time (0 to 1).
+    pub max_cpu_load: Option<f64>,
+    /// °C, from the `x86_pkg_temp` thermal zones (all zones if none).
+    pub max_temperature: Option<f64>,
+    /// W, from the RAPL powercap package counters.
+    pub max_power: Option<f64>,
+    /// Longest wait for the thresholds, after the sleep.
+    pub timeout: Duration,
+    /// Duration of a sample.
+    pub interval: Duration,
+    // where /proc and /sys are found
+    root: String,
+}
+
+impl Cooldown {
+    pub fn new() -> Self {
+        Self {
+            sleep: Duration::ZERO,
+            max_cpu_load: None,
+            max_temperature: None,
+            max_power: None,
+            timeout: Duration::from_secs(60),
+            interval: Duration::from_secs(1),
+            root: "/".into(),
+        }
+    }
+
+    pub fn set_root(&mut self, root: &str) {
+        self.root = root.into();
+    }
+
+    /// Conditions of the last sample.
+    pub fn wait(&self) -> Conditions {
+        let start = Instant::now();
+        std::thread::sleep(self.sleep);
+        loop {
+            let mut conditions = self.sample();
+            conditions.quiet = below(conditions.cpu_load, self.max_cpu_load)
+                && below(conditions.temperature, self.max_temperature)
+                && below(conditions.power, self.max_power);
+            if conditions.quiet || start.elapsed() >= self.sleep + self.timeout {
+                conditions.waited = start.elapsed().as_secs_f64();
+                return conditions;
+            }
+        }
+    }
+
+    fn sample(&self) -> Conditions {
+        let root = Path::new(&self.root);
+        let stat = root.join("proc/stat");
+        let powercap = root.join("sys/class/powercap");
+        let cpu_before = read_system_cpu_usage(&stat);
+        let zones_before = read_rapl_zones(&powercap);
+        let start = Instant::now();
+        std::thread::sleep(self.interval);
+        let cpu_after = read_system_cpu_usage(&stat);
+        let zones_after = read_rapl_zones(&powercap);
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let cpu_load = match (cpu_before, cpu_after) {
+            (Some((busy_before, total_before)), Some((busy_after, total_after)))
+                if total_after > total_before =>
+            {
+                Some(
+                    busy_after.saturating_sub(busy_before) as f64
+                        / (total_after - total_before) as f64,
+                )
+            }
+            _ => None,
+        };
+        let power = if zones_before.is_empty() {
+            None
+        } else {
+            let uj: u64 = zones_before
+                .iter()
+                .filter_map(|before| {
+                    let after = zones_after.iter().find(|z| z.path == before.path)?;
+                    Some(rapl_delta(
+                        before.energy_uj,
+                        after.energy_uj,
+                        before.max_energy_range_uj,
+                    ))
+                })
+                .sum();
+            Some(uj as f64 / 1_000_000.0 / elapsed)
+        };
+
+        Conditions {
+            cpu_load,
+            temperature: read_temperature(root),
+            power,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Cooldown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn below(value: Option<f64>, max: Option<f64>) -> bool {
+    match (value, max) {
+        (Some(value), Some(max)) => value <= max,
+        _ => true,
+    }
+}
+
+// Hottest package, in °C.
+fn read_temperature(root: &Path) -> Option<f64> {
+    let mut package = Vec::new();
+    let mut all = Vec::new();
+    let entries = std::fs::read_dir(root.join("sys/class/thermal")).ok()?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with("thermal_zone")
+        {
+            continue;
+        }
+        if let Some(millidegrees) = read_u64(path.join("temp")) {
+            let celsius = millidegrees as f64 / 1000.0;
+            let kind = std::fs::read_to_string(path.join("type")).unwrap_or_default();
+            if kind.trim() == "x86_pkg_temp" {
+                package.push(celsius);
+            }
+            all.push(celsius);
+        }
+    }
+    let temperatures = if package.is_empty() { all } else { package };
+    temperatures.into_iter().reduce(f64::max)
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::error::Error;
+
+    #[test]
+    fn cooldown_wait() -> Result<(), Box<dyn Error>> {
+        let root = "/tmp/etsdiff_cooldown";
+        let _ = std::fs::remove_dir_all(root);
+        for (zone, kind, temp) in [
+            ("thermal_zone0", "acpitz", "70000"),
+            ("thermal_zone1", "x86_pkg_temp", "45500"),
+        ] {
+            std::fs::create_dir_all(format!("{root}/sys/class/thermal/{zone}"))?;
+            std::fs::write(format!("{root}/sys/class/thermal/{zone}/type"), kind)?;
+            std::fs::write(format!("{root}/sys/class/thermal/{zone}/temp"), temp)?;
+        }
+        std::fs::create_dir_all(format!("{root}/sys/class/powercap/intel-rapl:0"))?;
+        std::fs::write(
+            format!("{root}/sys/class/powercap/intel-rapl:0/energy_uj"),
+            "1000",
+        )?;
+        std::fs::write(
+            format!("{root}/sys/class/powercap/intel-rapl:0/max_energy_range_uj"),
+            "262143328850",
+        )?;
+        assert_eq!(Some(45.5), read_temperature(Path::new(root)));
+
+        let mut cooldown = Cooldown::new();
+        cooldown.set_root(root);
+        cooldown.interval = Duration::from_millis(10);
+        cooldown.sleep = Duration::from_millis(20);
+        cooldown.max_temperature = Some(50.0);
+        cooldown.max_power = Some(1.0);
+        // no /proc/stat: ignored
+        cooldown.max_cpu_load = Some(0.1);
+
+        let conditions = cooldown.wait();
+        assert!(conditions.quiet);
+        assert!(conditions.waited >= 0.03);
+        assert_eq!(Some(45.5), conditions.temperature);
+        assert_eq!(Some(0.0), conditions.power);
+        assert!(conditions.cpu_load.is_none());
+
+        cooldown.max_temperature = Some(40.0);
+        cooldown.timeout = Duration::from_millis(50);
+        let conditions = cooldown.wait();
+        assert!(!conditions.quiet);
+        assert!(conditions.waited >= 0.07);
+
+        std::fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+}
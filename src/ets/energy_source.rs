@@ -0,0 +1,499 @@
+// SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use inotify::{Inotify, WatchMask};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use systemctl;
+
+use super::service::Service;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EnergySourceType {
+    Vjoule(Duration),
+    Rapl,
+    Replay(String),
+}
+
+// ===
+
+pub trait EnergySource {
+    fn source_type(&self) -> EnergySourceType;
+    fn before_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn after_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    /// Cumulative energy (in joules) consumed by each service, in the same order.
+    fn read(&mut self, services: &[&Service]) -> Result<Vec<f64>, Box<dyn Error>>;
+}
+
+// ===
+
+const VJOULE_SERVICE_NAME: &str = "vjoule_service";
+const VJOULE_RESULTS_CPU: &str = "/etc/vjoule/results/cpu";
+const VJOULE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+pub const VJOULE_DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct VjouleSource {
+    vjoule_need_stop: bool,
+    timeout: Duration,
+    inotify: Option<Inotify>,
+}
+
+impl EnergySource for VjouleSource {
+    fn source_type(&self) -> EnergySourceType {
+        EnergySourceType::Vjoule(self.timeout)
+    }
+    fn before_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        if !systemctl::exists(VJOULE_SERVICE_NAME)? {
+            bail!("EComponent require installation of vjoule");
+        }
+        if !systemctl::is_active(VJOULE_SERVICE_NAME)? {
+            self.vjoule_need_stop = true;
+            systemctl::restart(VJOULE_SERVICE_NAME)?;
+            if !systemctl::is_active(VJOULE_SERVICE_NAME)? {
+                bail!("Can't start {}", VJOULE_SERVICE_NAME);
+            }
+        }
+
+        // results are only created once vjoule is fully started
+        let start = Instant::now();
+        while !Path::new(VJOULE_RESULTS_CPU).is_file() {
+            if start.elapsed() > self.timeout {
+                bail!(
+                    "vjoule didn't create {} within {:?}",
+                    VJOULE_RESULTS_CPU,
+                    self.timeout
+                );
+            }
+            std::thread::sleep(VJOULE_POLL_INTERVAL);
+        }
+
+        let inotify = Inotify::init()?;
+        inotify
+            .watches()
+            .add(VJOULE_RESULTS_CPU, WatchMask::MODIFY)?;
+        self.inotify = Some(inotify);
+
+        self.wait_vjoule_signal()
+    }
+    fn after_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inotify = None;
+        if self.vjoule_need_stop {
+            systemctl::stop(VJOULE_SERVICE_NAME)?;
+            self.vjoule_need_stop = false;
+        }
+        Ok(())
+    }
+    fn read(&mut self, services: &[&Service]) -> Result<Vec<f64>, Box<dyn Error>> {
+        self.wait_vjoule_signal()?;
+        let mut values = Vec::new();
+        for s in services {
+            let pn = s.process_name.as_ref().unwrap();
+            let path = format!("/etc/vjoule/results/etsdiff.slice/{pn}/cpu");
+            let cpu_s = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Can't read vjoule results {path}: {e}"))?;
+            values.push(cpu_s.trim().parse::<f64>()?);
+        }
+        Ok(values)
+    }
+}
+
+impl VjouleSource {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            vjoule_need_stop: false,
+            timeout,
+            inotify: None,
+        }
+    }
+    // Wait for the next update of vjoule results: pending events are old
+    // updates, they are dropped before waiting for a new one.
+    fn wait_vjoule_signal(&mut self) -> Result<(), Box<dyn Error>> {
+        let timeout = self.timeout;
+        let inotify = match self.inotify {
+            Some(ref mut inotify) => inotify,
+            None => bail!("vjoule results are not watched, before_campaign() not called"),
+        };
+
+        let mut buffer = [0; 1024];
+        loop {
+            match inotify.read_events(&mut buffer) {
+                Ok(_) => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let start = Instant::now();
+        loop {
+            match inotify.read_events(&mut buffer) {
+                Ok(mut events) => {
+                    if events.next().is_some() {
+                        return Ok(());
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                Err(e) => return Err(e.into()),
+            }
+            if start.elapsed() > timeout {
+                bail!(
+                    "vjoule stalled: {} not updated within {:?}",
+                    VJOULE_RESULTS_CPU,
+                    timeout
+                );
+            }
+            std::thread::sleep(VJOULE_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Default for VjouleSource {
+    fn default() -> Self {
+        Self::new(VJOULE_DEFAULT_TIMEOUT)
+    }
+}
+
+// ===
+
+const RAPL_POWERCAP_PATH: &str = "/sys/class/powercap";
+const PROC_STAT_PATH: &str = "/proc/stat";
+const USER_HZ: u64 = 100;
+
+pub(crate) struct RaplZone {
+    pub(crate) path: String,
+    pub(crate) max_energy_range_uj: u64,
+    pub(crate) energy_uj: u64,
+}
+
+pub struct RaplSource {
+    zones: Vec<RaplZone>,
+    system_usage: Option<u64>,
+    cpu_usages: HashMap<String, Option<u64>>,
+    energies: HashMap<String, f64>,
+}
+
+impl EnergySource for RaplSource {
+    fn source_type(&self) -> EnergySourceType {
+        EnergySourceType::Rapl
+    }
+    fn before_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        self.zones = read_rapl_zones(Path::new(RAPL_POWERCAP_PATH));
+        if self.zones.is_empty() {
+            bail!(
+                "EComponent require RAPL powercap zones in {}",
+                RAPL_POWERCAP_PATH
+            );
+        }
+        self.system_usage = read_system_cpu_usage(Path::new(PROC_STAT_PATH)).map(|(busy, _)| busy);
+        Ok(())
+    }
+    fn read(&mut self, services: &[&Service]) -> Result<Vec<f64>, Box<dyn Error>> {
+        let mut package_uj = 0;
+        for zone in &mut self.zones {
+            let path = format!("{}/energy_uj", zone.path);
+            let energy_uj = read_u64(&path).ok_or(format!("Can't read RAPL counter {path}"))?;
+            package_uj += rapl_delta(zone.energy_uj, energy_uj, zone.max_energy_range_uj);
+            zone.energy_uj = energy_uj;
+        }
+        let package_joules = package_uj as f64 / 1_000_000.0;
+
+        let system_usage = read_system_cpu_usage(Path::new(PROC_STAT_PATH)).map(|(busy, _)| busy);
+        let system_delta = match (self.system_usage, system_usage) {
+            (Some(before), Some(after)) => Some(after.saturating_sub(before)),
+            _ => None,
+        };
+        self.system_usage = system_usage;
+
+        // services seen for the first time start from their current usage
+        let mut deltas = Vec::new();
+        for s in services {
+            let pn = s.process_name.as_ref().unwrap();
+            let usage = read_cgroup_cpu_usage(s);
+            deltas.push(
+                match (self.cpu_usages.get(pn).copied().unwrap_or(usage), usage) {
+                    (Some(before), Some(after)) => Some(after.saturating_sub(before)),
+                    _ => None,
+                },
+            );
+            self.cpu_usages.insert(pn.clone(), usage);
+        }
+        let deltas: Option<Vec<u64>> = deltas.into_iter().collect();
+
+        let values = attribute_energy(package_joules, deltas, system_delta, services.len());
+        Ok(services
+            .iter()
+            .zip(values)
+            .map(|(s, v)| {
+                let energy = self
+                    .energies
+                    .entry(s.process_name.clone().unwrap())
+                    .or_insert(0.0);
+                *energy += v;
+                *energy
+            })
+            .collect())
+    }
+}
+
+impl RaplSource {
+    pub fn new() -> Self {
+        Self {
+            zones: Vec::new(),
+            system_usage: None,
+            cpu_usages: HashMap::new(),
+            energies: HashMap::new(),
+        }
+    }
+}
+
+impl Default for RaplSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Split the package energy between services according to their share of the
+// busy CPU time. Without CPU accounting the energy is equally distributed.
+fn attribute_energy(
+    package_joules: f64,
+    cpu_deltas: Option<Vec<u64>>,
+    system_delta: Option<u64>,
+    nb_services: usize,
+) -> Vec<f64> {
+    if nb_services == 0 {
+        return Vec::new();
+    }
+    match (cpu_deltas, system_delta) {
+        (Some(deltas), Some(system_delta)) if system_delta > 0 => {
+            let total: u64 = deltas.iter().sum();
+            let denominator = system_delta.max(total) as f64;
+            deltas
+                .iter()
+                .map(|d| package_joules * *d as f64 / denominator)
+                .collect()
+        }
+        _ => vec![package_joules / nb_services as f64; nb_services],
+    }
+}
+
+/// Energy between two reads of a RAPL counter, that wraps around at
+/// `max_energy_range_uj`.
+pub fn rapl_delta(before: u64, after: u64, max_energy_range_uj: u64) -> u64 {
+    if after >= before {
+        after - before
+    } else {
+        max_energy_range_uj - before + after
+    }
+}
+
+pub(crate) fn read_u64<P: AsRef<Path>>(path: P) -> Option<u64> {
+    std::fs::read_to_string(path)
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+}
+
+// Only the top level zones (intel-rapl:N) are packages, sub zones
+// (intel-rapl:N:M) are already included in their package counter.
+pub(crate) fn read_rapl_zones(powercap: &Path) -> Vec<RaplZone> {
+    let mut zones = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(powercap) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with("intel-rapl:") && name.matches(':').count() == 1 {
+                let path = entry.path().to_string_lossy().to_string();
+                if let (Some(max_energy_range_uj), Some(energy_uj)) = (
+                    read_u64(format!("{path}/max_energy_range_uj")),
+                    read_u64(format!("{path}/energy_uj")),
+                ) {
+                    zones.push(RaplZone {
+                        path,
+                        max_energy_range_uj,
+                        energy_uj,
+                    });
+                }
+            }
+        }
+    }
+    zones
+}
+
+// Busy and total CPU time of the whole system in microseconds.
+pub(crate) fn read_system_cpu_usage(stat: &Path) -> Option<(u64, u64)> {
+    parse_system_cpu_usage(&std::fs::read_to_string(stat).ok()?)
+}
+
+fn parse_system_cpu_usage(stat: &str) -> Option<(u64, u64)> {
+    let line = stat.lines().next()?;
+    let fields: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|f| f.parse::<u64>().unwrap_or(0))
+        .collect();
+    if fields.len() < 8 {
+        return None;
+    }
+    // user + nice + system + irq + softirq + steal (guest is in user)
+    let busy = fields[0] + fields[1] + fields[2] + fields[5] + fields[6] + fields[7];
+    let total = busy + fields[3] + fields[4];
+    Some((busy * 1_000_000 / USER_HZ, total * 1_000_000 / USER_HZ))
+}
+
+fn read_cgroup_cpu_usage(service: &Service) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("{}/cpu.stat", service.cgroup_path()?)).ok()?;
+    for line in stat.lines() {
+        if let Some(usage) = line.strip_prefix("usage_usec ") {
+            return usage.trim().parse::<u64>().ok();
+        }
+    }
+    None
+}
+
+// ===
+
+/// Replays pre-recorded cumulative counters: one file per process name in
+/// `path`, holding one value (in joules) per line. Each read consumes the
+/// next value, the last one is repeated once the file is exhausted.
+pub struct ReplaySource {
+    path: String,
+    counters: HashMap<String, (Vec<f64>, usize)>,
+}
+
+impl EnergySource for ReplaySource {
+    fn source_type(&self) -> EnergySourceType {
+        EnergySourceType::Replay(self.path.clone())
+    }
+    fn before_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        if !Path::new(&self.path).is_dir() {
+            bail!("EComponent replay directory {} not found", self.path);
+        }
+        Ok(())
+    }
+    fn read(&mut self, services: &[&Service]) -> Result<Vec<f64>, Box<dyn Error>> {
+        let mut ret = Vec::new();
+        for s in services {
+            let pn = s.process_name.as_ref().unwrap();
+            if !self.counters.contains_key(pn) {
+                let path = format!("{}/{pn}", self.path);
+                let content = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Can't read replay file {path}: {e}"))?;
+                let mut values = Vec::new();
+                for l in content.lines().filter(|l| !l.trim().is_empty()) {
+                    values.push(l.trim().parse::<f64>()?);
+                }
+                self.counters.insert(pn.clone(), (values, 0));
+            }
+            let (values, cursor) = self.counters.get_mut(pn).unwrap();
+            ret.push(
+                values
+                    .get(*cursor)
+                    .or(values.last())
+                    .copied()
+                    .unwrap_or(0.0),
+            );
+            *cursor += 1;
+        }
+        Ok(ret)
+    }
+}
+
+impl ReplaySource {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.into(),
+            counters: HashMap::new(),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::error::Error;
+
+    #[test]
+    fn rapl_source_delta() {
+        assert_eq!(150, rapl_delta(1_000, 1_150, 262_143_328_850));
+        assert_eq!(0, rapl_delta(1_000, 1_000, 262_143_328_850));
+        // counter wraparound
+        assert_eq!(300, rapl_delta(900, 200, 1_000));
+    }
+
+    #[test]
+    fn rapl_source_system_cpu_usage() {
+        let usec = 1_000_000 / USER_HZ;
+        assert_eq!(
+            Some((
+                (4 + 1 + 2 + 3 + 2 + 1) * usec,
+                (4 + 1 + 2 + 80 + 5 + 3 + 2 + 1) * usec
+            )),
+            parse_system_cpu_usage("cpu  4 1 2 80 5 3 2 1 0 0\ncpu0 4 1 2 80 5 3 2 1 0 0\n")
+        );
+        assert!(parse_system_cpu_usage("cpu 4 1 2\n").is_none());
+        assert!(parse_system_cpu_usage("").is_none());
+    }
+
+    #[test]
+    fn rapl_source_attribute_energy() {
+        let values = attribute_energy(10.0, Some(vec![250, 500]), Some(1_000), 2);
+        assert_eq!(vec![2.5, 5.0], values);
+
+        // services can't get more than the package energy
+        let values = attribute_energy(10.0, Some(vec![1_500, 500]), Some(1_000), 2);
+        assert_eq!(vec![7.5, 2.5], values);
+
+        // without cpu accounting energy is equally distributed
+        let values = attribute_energy(10.0, None, Some(1_000), 4);
+        assert_eq!(vec![2.5, 2.5, 2.5, 2.5], values);
+
+        assert!(attribute_energy(10.0, None, None, 0).is_empty());
+    }
+
+    #[test]
+    fn vjoule_source_read_without_watch() {
+        let s1 = Service::new("Service 1");
+        let mut source = VjouleSource::new(Duration::from_millis(10));
+
+        // no endless wait when vjoule isn't started
+        assert!(source.read(&[&s1]).is_err());
+    }
+
+    #[test]
+    fn replay_source_read() -> Result<(), Box<dyn Error>> {
+        let path = "/tmp/etsdiff_replay_source";
+        std::fs::create_dir_all(path)?;
+        std::fs::write(format!("{path}/p1"), "1.5\n2.5\n4.0\n")?;
+        std::fs::write(format!("{path}/p2"), "10\n")?;
+
+        let mut s1 = Service::new("Service 1");
+        s1.set_process_name("p1");
+        let mut s2 = Service::new("Service 2");
+        s2.set_process_name("p2");
+
+        let mut source = ReplaySource::new(path);
+        source.before_campaign()?;
+        assert_eq!(vec![1.5, 10.0], source.read(&[&s1, &s2])?);
+        assert_eq!(vec![2.5, 10.0], source.read(&[&s1, &s2])?);
+        assert_eq!(vec![4.0], source.read(&[&s1])?);
+        assert_eq!(vec![4.0], source.read(&[&s1])?);
+
+        let mut s3 = Service::new("Service 3");
+        s3.set_process_name("p3");
+        assert!(source.read(&[&s3]).is_err());
+        assert!(ReplaySource::new("/unknowpath").before_campaign().is_err());
+
+        std::fs::remove_dir_all(path)?;
+
+        Ok(())
+    }
+}
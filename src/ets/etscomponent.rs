@@ -1,130 +1,163 @@
 // SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use fs_extra::dir::get_size;
-use inotify::{Inotify, WatchMask};
-use rtshark::{RTShark, RTSharkBuilder};
+use std::any::Any;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
 use std::rc::{Rc, Weak};
-use std::str::FromStr;
-use systemctl;
-
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::cgroup::{
+    parse_io_stat, parse_keyed, read_file, read_keyed, read_value, MemoryPeak, CGROUP_ROOT,
+};
+use super::energy_source::{EnergySource, EnergySourceType};
+use super::packet_capture::{
+    CaptureRule, CaptureSettings, CaptureType, PacketCapture, TransferCounter,
+};
+use super::report::{
+    BaselineReport, ProtocolReport, ServiceStorage, ServiceTransfer, StorageChanges, TestReport,
+};
 use super::service::{Service, ServicesLink};
+use super::storage::{probe_octets, Snapshot, StorageMode};
+use super::system_call::SystemCall;
 use super::test::Test;
 
-pub trait ETSComponent {
+/// `Any` gives access to the settings of a component of the registry.
+pub trait ETSComponent: Any {
+    /// Name used to enable the component in the configuration, and as report
+    /// column for components without their own report fields.
+    fn name(&self) -> &str;
     fn min_iteration(&self) -> i32 {
         1
     }
     fn value(&self) -> f64 {
         0.0
     }
-    fn before_campaign(&mut self) {}
-    fn after_campaign(&mut self) {}
-    fn before_test(&mut self, _test: &dyn Test) {}
-    fn after_test(&mut self, _test: &dyn Test) {}
+    /// Unit of the value, given in the report for components without their
+    /// own report fields.
+    fn unit(&self) -> Option<&str> {
+        None
+    }
+    fn before_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn after_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn before_test(&mut self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn after_test(&mut self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    /// Check the component can measure a test, before running the campaign.
+    fn validate(&self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    /// Adds the values of the last test to its report.
+    fn report(&self, tr: &mut TestReport) {
+        println!(
+            "      {} -> {} {}",
+            self.name(),
+            self.value(),
+            self.unit().unwrap_or_default()
+        );
+        tr.metrics.insert(self.name().into(), self.value());
+    }
+    /// Adds the values of the last idle measurement to the baseline report.
+    fn report_baseline(&self, _br: &mut BaselineReport) {}
 }
 
 // ===
 
-const VJOULE_SERVICE_NAME: &str = "vjoule_service";
-
 pub struct EComponent {
     values: Vec<f64>,
+    names: Vec<String>,
     services: Weak<RefCell<Vec<Service>>>,
-    vjoule_need_stop: bool,
+    source: Box<dyn EnergySource>,
 }
 
 impl ETSComponent for EComponent {
+    fn name(&self) -> &str {
+        "energy"
+    }
     fn value(&self) -> f64 {
         self.values.iter().sum()
     }
-    fn before_campaign(&mut self) {
-        if !systemctl::is_active(VJOULE_SERVICE_NAME).unwrap() {
-            self.vjoule_need_stop = true;
-            systemctl::restart(VJOULE_SERVICE_NAME).unwrap();
-            // TODO better implementation need to wait service started to inotify results/cpu
-            std::thread::sleep(std::time::Duration::from_millis(1000));
-            if !systemctl::is_active(VJOULE_SERVICE_NAME).unwrap() {
-                panic!("Can't start vjoule_service");
-            }
-        }
-        self.wait_vjoule_signal();
+    fn before_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        self.source.before_campaign()
     }
-    fn after_campaign(&mut self) {
-        if self.vjoule_need_stop {
-            systemctl::stop(VJOULE_SERVICE_NAME).unwrap();
-        }
+    fn after_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        self.source.after_campaign()
     }
-    fn before_test(&mut self, test: &dyn Test) {
+    fn before_test(&mut self, test: &dyn Test) -> Result<(), Box<dyn Error>> {
         let services_rc = Weak::upgrade(&self.services).unwrap();
         let services = services_rc.borrow();
-        self.values.resize(services.len(), 0.0);
-        self.wait_vjoule_signal();
-        let mut i = 0;
-        for s in &*services {
-            if test.services_names().contains(&s.name) {
-                if let Some(pn) = &s.process_name {
-                    let cpu_s = std::fs::read_to_string(format!(
-                        "/etc/vjoule/results/etsdiff.slice/{pn}/cpu"
-                    ))
-                    .unwrap();
-                    self.values[i] = cpu_s[..cpu_s.len() - 1].parse::<f64>().unwrap();
-                    i += 1;
-                }
-            }
-        }
+        let measured = measured_services(&services, test);
+        self.names = measured.iter().map(|s| s.name.clone()).collect();
+        self.values = self.source.read(&measured)?;
+        Ok(())
     }
-    fn after_test(&mut self, test: &dyn Test) {
+    fn after_test(&mut self, test: &dyn Test) -> Result<(), Box<dyn Error>> {
         let services_rc = Weak::upgrade(&self.services).unwrap();
         let services = services_rc.borrow();
-        self.wait_vjoule_signal();
-        let mut i = 0;
-        for s in &*services {
-            if test.services_names().contains(&s.name) {
-                if let Some(pn) = &s.process_name {
-                    let cpu_s = std::fs::read_to_string(format!(
-                        "/etc/vjoule/results/etsdiff.slice/{pn}/cpu"
-                    ))
-                    .unwrap();
-                    self.values[i] =
-                        cpu_s[..cpu_s.len() - 1].parse::<f64>().unwrap() - self.values[i];
-                    i += 1;
-                }
-            }
+        let measured = measured_services(&services, test);
+        let values = self.source.read(&measured)?;
+        for (value, after) in self.values.iter_mut().zip(values) {
+            *value = after - *value;
+        }
+        Ok(())
+    }
+    fn report(&self, tr: &mut TestReport) {
+        println!("      EComponent -> {} Joules", self.to_joules());
+        tr.energy = self.to_joules();
+        tr.energy_by_service = self.to_joules_by_service();
+        for (name, joules) in &tr.energy_by_service {
+            println!("        {name} -> {joules} Joules");
         }
     }
+    fn report_baseline(&self, br: &mut BaselineReport) {
+        br.energy = self.to_joules();
+    }
 }
 
 impl EComponent {
-    pub fn new(services: &ServicesLink) -> Self {
-        if !systemctl::exists(VJOULE_SERVICE_NAME).unwrap() {
-            panic!("EComponent require installation of vjoule");
-        }
-
+    pub fn new(services: &ServicesLink, source: Box<dyn EnergySource>) -> Self {
         Self {
             values: vec![0.0],
+            names: Vec::new(),
             services: Rc::<RefCell<Vec<Service>>>::downgrade(services),
-            vjoule_need_stop: false,
+            source,
         }
     }
+    pub fn source_type(&self) -> EnergySourceType {
+        self.source.source_type()
+    }
     pub fn to_joules(&self) -> f64 {
         self.value()
     }
-    fn wait_vjoule_signal(&self) {
-        let mut inotify = Inotify::init().expect("Error while initializing inotify instance");
-
-        inotify
-            .add_watch("/etc/vjoule/results/cpu", WatchMask::MODIFY)
-            .expect("Failed to add watch");
-
-        let mut buffer = [0; 1024];
-        inotify
-            .read_events_blocking(&mut buffer)
-            .expect("Error while reading events");
+    pub fn to_joules_by_service(&self) -> BTreeMap<String, f64> {
+        self.names
+            .iter()
+            .cloned()
+            .zip(self.values.iter().copied())
+            .collect()
     }
 }
 
+// Services of the test having a process (and so a cgroup) to measure.
+fn measured_services<'a>(services: &'a [Service], test: &dyn Test) -> Vec<&'a Service> {
+    services
+        .iter()
+        .filter(|s| test.services_names().contains(&s.name) && s.process_name.is_some())
+        .collect()
+}
+
 // ===
 
 pub trait OctetsComponent: ETSComponent {
@@ -136,143 +169,693 @@ pub trait OctetsComponent: ETSComponent {
 // ===
 
 pub struct TComponent {
-    value: u64,
+    counter: TransferCounter,
+    names: Vec<String>,
+    rules: Vec<CaptureRule>,
+    settings: CaptureSettings,
     services: Weak<RefCell<Vec<Service>>>,
-    rtshark: Option<RTShark>,
+    capture: Box<dyn PacketCapture>,
 }
 
 impl ETSComponent for TComponent {
-    fn before_test(&mut self, test: &dyn Test) {
-        self.value = 0;
+    fn name(&self) -> &str {
+        "transfer"
+    }
+    fn before_test(&mut self, test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        self.counter = TransferCounter::new();
+        (self.names, self.rules) = self.capture_rules(test);
 
-        let mut filter = String::from("");
+        self.capture.start(&self.rules)
+    }
+    fn after_test(&mut self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        self.counter = self.capture.stop()?;
+        Ok(())
+    }
+
+    fn value(&self) -> f64 {
+        self.to_octets() as f64
+    }
+    /// Check the capture of a test can be started.
+    fn validate(&self, test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        let (_, rules) = self.capture_rules(test);
+        self.capture.validate(&rules)
+    }
+    fn report(&self, tr: &mut TestReport) {
+        println!("      TComponent -> {} Ko", self.to_octets() / 1024);
+        tr.transfer = self.to_octets();
+        tr.transfer_by_service = self.to_octets_by_service();
+        tr.protocols = Some(self.to_protocols());
+        for (name, st) in &tr.transfer_by_service {
+            println!(
+                "        {name} -> {} Ko in, {} Ko out",
+                st.ingress / 1024,
+                st.egress / 1024
+            );
+        }
+    }
+    fn report_baseline(&self, br: &mut BaselineReport) {
+        br.transfer = self.to_octets();
+    }
+}
+
+impl OctetsComponent for TComponent {
+    fn to_octets(&self) -> u64 {
+        self.counter.total
+    }
+}
+
+impl TComponent {
+    pub fn new(services: &ServicesLink, capture: Box<dyn PacketCapture>) -> Self {
+        Self {
+            counter: TransferCounter::new(),
+            names: Vec::new(),
+            rules: Vec::new(),
+            settings: CaptureSettings::localhost(),
+            services: Rc::<RefCell<Vec<Service>>>::downgrade(services),
+            capture,
+        }
+    }
+    pub fn capture_type(&self) -> CaptureType {
+        self.capture.capture_type()
+    }
+    /// Campaign capture settings, used by services not setting their own.
+    pub fn set_settings(&mut self, settings: CaptureSettings) {
+        self.settings = settings;
+    }
+    fn capture_rules(&self, test: &dyn Test) -> (Vec<String>, Vec<CaptureRule>) {
+        let mut names = Vec::new();
+        let mut rules = Vec::new();
         let services_rc = Weak::upgrade(&self.services).unwrap();
         let services = services_rc.borrow();
         for s in &*services {
             if test.services_names().contains(&s.name) {
-                for p in &s.ports {
-                    if !filter.is_empty() {
-                        filter = format!("{filter} or port {p}");
-                    } else {
-                        filter = format!("port {p}");
-                    }
-                }
+                names.push(s.name.clone());
+                rules.push(CaptureRule::new(&s.ports, &s.capture, &self.settings));
             }
         }
-        filter = format!("host 127.0.0.1 and ({filter})");
+        (names, rules)
+    }
+    pub fn to_protocols(&self) -> ProtocolReport {
+        self.counter.protocols.report()
+    }
+    /// Octets received (ingress) and sent (egress) on the ports of each
+    /// service of the last test.
+    pub fn to_octets_by_service(&self) -> BTreeMap<String, ServiceTransfer> {
+        self.names
+            .iter()
+            .zip(&self.rules)
+            .map(|(name, rule)| {
+                (
+                    name.clone(),
+                    ServiceTransfer {
+                        ingress: self.counter.ingress(&rule.ports),
+                        egress: self.counter.egress(&rule.ports),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+// ===
+
+pub struct SComponent {
+    mode: StorageMode,
+    before: Snapshot,
+    after: Snapshot,
+    after_by_service: BTreeMap<String, Snapshot>,
+    probes_before: u64,
+    probes_after: BTreeMap<String, u64>,
+    services: Weak<RefCell<Vec<Service>>>,
+}
+
+impl ETSComponent for SComponent {
+    fn name(&self) -> &str {
+        "storage"
+    }
+    fn value(&self) -> f64 {
+        self.to_octets() as f64
+    }
+    fn before_test(&mut self, test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        self.before = Self::merge(&self.snapshots(test)?);
+        self.after = Snapshot::default();
+        self.after_by_service.clear();
+        self.probes_before = self.probes(test)?.values().sum();
+        self.probes_after.clear();
+        Ok(())
+    }
+    fn after_test(&mut self, test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        self.after_by_service = self.snapshots(test)?;
+        self.after = Self::merge(&self.after_by_service);
+        self.probes_after = self.probes(test)?;
+        Ok(())
+    }
+    fn report(&self, tr: &mut TestReport) {
+        println!("      SComponent -> {} Ko", self.to_octets() / 1024);
+        tr.storage = self.to_octets();
+        tr.storage_apparent = Some(self.apparent());
+        tr.storage_by_service = self.to_octets_by_service();
+        for (name, ss) in &tr.storage_by_service {
+            println!(
+                "        {name} -> {} Ko in paths, {} Ko from probes",
+                ss.paths / 1024,
+                ss.probes / 1024
+            );
+        }
+        tr.storage_changes = Some(self.changes());
+    }
+    fn report_baseline(&self, br: &mut BaselineReport) {
+        br.storage_drift = self.changes().delta;
+    }
+}
 
-        let builder = RTSharkBuilder::builder()
-            .input_path("any")
-            .output_path("/tmp/etsdiff.pcap")
-            .live_capture()
-            .capture_filter(&filter);
+impl OctetsComponent for SComponent {
+    /// Size of the storage paths after the test, measured with the mode,
+    /// plus the storage probes.
+    fn to_octets(&self) -> u64 {
+        self.after.size(self.mode) + self.probes_after.values().sum::<u64>()
+    }
+}
 
-        match builder.spawn() {
-            Err(err) => {
-                eprintln!("Error running tshark writter: {err}");
+impl SComponent {
+    pub fn new(services: &ServicesLink, mode: StorageMode) -> Self {
+        Self {
+            mode,
+            before: Snapshot::default(),
+            after: Snapshot::default(),
+            after_by_service: BTreeMap::new(),
+            probes_before: 0,
+            probes_after: BTreeMap::new(),
+            services: Rc::<RefCell<Vec<Service>>>::downgrade(services),
+        }
+    }
+    pub fn mode(&self) -> StorageMode {
+        self.mode
+    }
+    /// What the test changed in the storage paths, `delta` including the
+    /// storage probes.
+    pub fn changes(&self) -> StorageChanges {
+        let mut changes = self.before.changes(&self.after, self.mode);
+        let probes_after: u64 = self.probes_after.values().sum();
+        changes.delta += probes_after as i64 - self.probes_before as i64;
+        changes
+    }
+    /// Apparent size (file lengths) of the storage paths after the test.
+    pub fn apparent(&self) -> u64 {
+        self.after.size(StorageMode::Apparent)
+    }
+    /// Storage after the test of each service with storage paths or probes.
+    pub fn to_octets_by_service(&self) -> BTreeMap<String, ServiceStorage> {
+        let services_rc = Weak::upgrade(&self.services).unwrap();
+        let services = services_rc.borrow();
+        let mut ret = BTreeMap::new();
+        for s in &*services {
+            if let (Some(snapshot), Some(probes)) = (
+                self.after_by_service.get(&s.name),
+                self.probes_after.get(&s.name),
+            ) {
+                let mut ss = ServiceStorage {
+                    paths: snapshot.size(self.mode),
+                    probes: *probes,
+                    ..Default::default()
+                };
+                if !s.storage_filter.is_empty() {
+                    ss.files = snapshot.files();
+                }
+                ret.insert(s.name.clone(), ss);
+            }
+        }
+        ret
+    }
+    /// Runs the storage probes of the test services, sum by service (services
+    /// without storage paths nor probes are ignored).
+    fn probes(&self, test: &dyn Test) -> Result<BTreeMap<String, u64>, Box<dyn Error>> {
+        let services_rc = Weak::upgrade(&self.services).unwrap();
+        let mut services = services_rc.borrow_mut();
+        let mut ret = BTreeMap::new();
+        for s in &mut *services {
+            if !test.services_names().contains(&s.name)
+                || (s.storage_paths.is_empty() && s.storage_probes.is_empty())
+            {
+                continue;
             }
-            Ok(rtshark) => {
-                self.rtshark = Some(rtshark);
-                std::thread::sleep(std::time::Duration::from_millis(1000)); // TODO better implementation
+            let mut octets = 0;
+            for probe in &mut s.storage_probes {
+                octets += probe_octets(&probe.output()?)?;
             }
-        };
+            ret.insert(s.name.clone(), octets);
+        }
+        Ok(ret)
     }
-    fn after_test(&mut self, _test: &dyn Test) {
-        match &mut self.rtshark {
-            Some(s) => {
-                s.kill();
-                self.rtshark = None;
+    /// Snapshots the storage paths of the test services, with their filter.
+    fn snapshots(&self, test: &dyn Test) -> Result<BTreeMap<String, Snapshot>, Box<dyn Error>> {
+        let services_rc = Weak::upgrade(&self.services).unwrap();
+        let services = services_rc.borrow();
+        let mut ret = BTreeMap::new();
+        for s in &*services {
+            if test.services_names().contains(&s.name) {
+                let snapshot = Snapshot::take(&s.storage_paths, &s.storage_filter)?;
+                ret.insert(s.name.clone(), snapshot);
             }
-            None => (),
         }
-        std::thread::sleep(std::time::Duration::from_millis(1000)); // TODO better implementation
+        Ok(ret)
+    }
+    fn merge(snapshots: &BTreeMap<String, Snapshot>) -> Snapshot {
+        let mut ret = Snapshot::default();
+        for snapshot in snapshots.values() {
+            ret.merge(snapshot);
+        }
+        ret
+    }
+}
 
-        let builder = RTSharkBuilder::builder().input_path("/tmp/etsdiff.pcap");
-        let mut rtshark = match builder.spawn() {
-            Err(err) => {
-                eprintln!("Error running tshark reader: {err}");
-                return;
-            }
-            Ok(rtshark) => rtshark,
+// ===
+
+/// Memory of a service cgroup: peak (`memory.peak`, or the highest sampled
+/// `memory.current`) and average RSS (anonymous + mapped file pages of
+/// `memory.stat`) sampled during the test, in octets.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct ServiceMemory {
+    pub peak: u64,
+    pub average_rss: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+struct MemorySamples {
+    rss_sum: u64,
+    current_max: u64,
+    count: u64,
+}
+
+const MEMORY_SAMPLING: Duration = Duration::from_millis(100);
+
+pub struct MComponent {
+    cgroup_root: String,
+    names: Vec<String>,
+    peaks: Vec<MemoryPeak>,
+    memory: BTreeMap<String, ServiceMemory>,
+    stop: Arc<AtomicBool>,
+    sampler: Option<JoinHandle<Vec<MemorySamples>>>,
+    services: Weak<RefCell<Vec<Service>>>,
+}
+
+impl ETSComponent for MComponent {
+    fn name(&self) -> &str {
+        "memory"
+    }
+    /// Sum of the services peaks.
+    fn value(&self) -> f64 {
+        self.memory.values().map(|m| m.peak).sum::<u64>() as f64
+    }
+    fn before_test(&mut self, test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        let (names, cgroups) = self.cgroups(test)?;
+        self.names = names;
+        self.memory.clear();
+        self.peaks = cgroups.iter().map(|c| MemoryPeak::reset(c)).collect();
+
+        self.stop = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&self.stop);
+        self.sampler = Some(std::thread::spawn(move || sample_memory(&cgroups, &stop)));
+        Ok(())
+    }
+    fn after_test(&mut self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        self.stop.store(true, Ordering::Relaxed);
+        let samples = match self.sampler.take() {
+            None => bail!("Memory sampling not started"),
+            Some(handle) => match handle.join() {
+                Ok(samples) => samples,
+                Err(_) => bail!("Memory sampling thread panicked"),
+            },
         };
-        while let Some(packet) = rtshark.read().unwrap_or_else(|e| {
-            eprintln!("Error parsing TShark output: {e}");
-            None
-        }) {
-            for layer in packet {
-                if layer.name() == "frame" {
-                    if let Some(fl) = layer.metadata("frame.len") {
-                        self.value += u64::from_str(fl.value()).unwrap_or(0);
-                    }
-                }
-            }
+        for ((name, peak), samples) in self.names.iter().zip(&mut self.peaks).zip(samples) {
+            let memory = ServiceMemory {
+                peak: peak.read().unwrap_or(0).max(samples.current_max),
+                average_rss: samples.rss_sum.checked_div(samples.count).unwrap_or(0),
+            };
+            self.memory.insert(name.clone(), memory);
+        }
+        Ok(())
+    }
+    fn report(&self, tr: &mut TestReport) {
+        let average_rss: u64 = self.memory.values().map(|m| m.average_rss).sum();
+        println!(
+            "      MComponent -> {} Ko peak, {} Ko average RSS",
+            self.value() as u64 / 1024,
+            average_rss / 1024
+        );
+        tr.metrics.insert("memory.peak".into(), self.value());
+        tr.metrics
+            .insert("memory.average_rss".into(), average_rss as f64);
+        for (name, memory) in &self.memory {
+            println!(
+                "        {name} -> {} Ko peak, {} Ko average RSS",
+                memory.peak / 1024,
+                memory.average_rss / 1024
+            );
+            tr.metrics
+                .insert(format!("memory.{name}.peak"), memory.peak as f64);
+            tr.metrics.insert(
+                format!("memory.{name}.average_rss"),
+                memory.average_rss as f64,
+            );
         }
+    }
+}
 
-        std::fs::remove_file("/tmp/etsdiff.pcap").expect("No /tmp/etsdiff.pcap file to delete");
+impl MComponent {
+    pub fn new(services: &ServicesLink) -> Self {
+        Self {
+            cgroup_root: CGROUP_ROOT.into(),
+            names: Vec::new(),
+            peaks: Vec::new(),
+            memory: BTreeMap::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            sampler: None,
+            services: Rc::<RefCell<Vec<Service>>>::downgrade(services),
+        }
+    }
+    /// Directory holding one cgroup per `process_name`.
+    pub fn set_cgroup_root(&mut self, root: &str) {
+        self.cgroup_root = root.into();
+    }
+    pub fn memory_by_service(&self) -> BTreeMap<String, ServiceMemory> {
+        self.memory.clone()
+    }
+    fn cgroups(&self, test: &dyn Test) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
+        let services_rc = Weak::upgrade(&self.services).unwrap();
+        let services = services_rc.borrow();
+        service_cgroups(&services, test, &self.cgroup_root, "memory.current")
     }
+}
 
-    fn value(&self) -> f64 {
-        self.to_octets() as f64
+// Names and cgroups of the measured services of the test, `file` must be
+// provided by their cgroup (v2, with the controller enabled).
+fn service_cgroups(
+    services: &[Service],
+    test: &dyn Test,
+    root: &str,
+    file: &str,
+) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
+    let mut names = Vec::new();
+    let mut cgroups = Vec::new();
+    for s in measured_services(services, test) {
+        let cgroup = format!("{}/{}", root, s.process_name.as_ref().unwrap());
+        if !Path::new(&cgroup).join(file).is_file() {
+            bail!("No {} for {} (cgroup v2 needed): {}", file, s.name, cgroup);
+        }
+        names.push(s.name.clone());
+        cgroups.push(cgroup);
     }
+    Ok((names, cgroups))
 }
 
-impl OctetsComponent for TComponent {
-    fn to_octets(&self) -> u64 {
-        self.value
+// Samples the memory of the cgroups until stopped (at least once).
+fn sample_memory(cgroups: &[String], stop: &AtomicBool) -> Vec<MemorySamples> {
+    let mut samples = vec![MemorySamples::default(); cgroups.len()];
+    loop {
+        let stopping = stop.load(Ordering::Relaxed);
+        for (s, cgroup) in samples.iter_mut().zip(cgroups) {
+            let stat = read_keyed(cgroup, "memory.stat");
+            let current = read_value(cgroup, "memory.current");
+            // the cgroup can be deleted meanwhile
+            if let (Ok(stat), Ok(current)) = (stat, current) {
+                let rss = stat.get("anon").unwrap_or(&0) + stat.get("file_mapped").unwrap_or(&0);
+                s.rss_sum += rss;
+                s.current_max = s.current_max.max(current);
+                s.count += 1;
+            }
+        }
+        if stopping {
+            return samples;
+        }
+        std::thread::sleep(MEMORY_SAMPLING);
     }
 }
 
-impl TComponent {
-    pub fn new(services: &ServicesLink) -> Self {
+// ===
+
+/// Counters of a cgroup file read before and after a test, by service.
+struct CgroupCounters {
+    file: &'static str,
+    parse: fn(&str) -> BTreeMap<String, u64>,
+    cgroup_root: String,
+    names: Vec<String>,
+    cgroups: Vec<String>,
+    before: Vec<BTreeMap<String, u64>>,
+    deltas: BTreeMap<String, BTreeMap<String, u64>>,
+    services: Weak<RefCell<Vec<Service>>>,
+}
+
+impl CgroupCounters {
+    fn new(
+        services: &ServicesLink,
+        file: &'static str,
+        parse: fn(&str) -> BTreeMap<String, u64>,
+    ) -> Self {
         Self {
-            value: 0,
+            file,
+            parse,
+            cgroup_root: CGROUP_ROOT.into(),
+            names: Vec::new(),
+            cgroups: Vec::new(),
+            before: Vec::new(),
+            deltas: BTreeMap::new(),
             services: Rc::<RefCell<Vec<Service>>>::downgrade(services),
-            rtshark: None,
+        }
+    }
+    fn read(&self) -> Result<Vec<BTreeMap<String, u64>>, Box<dyn Error>> {
+        self.cgroups
+            .iter()
+            .map(|cgroup| Ok((self.parse)(&read_file(cgroup, self.file)?)))
+            .collect()
+    }
+    fn start(&mut self, test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        let services_rc = Weak::upgrade(&self.services).unwrap();
+        let services = services_rc.borrow();
+        (self.names, self.cgroups) =
+            service_cgroups(&services, test, &self.cgroup_root, self.file)?;
+        self.deltas.clear();
+        self.before = self.read()?;
+        Ok(())
+    }
+    fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        let after = self.read()?;
+        for ((name, before), after) in self.names.iter().zip(&self.before).zip(after) {
+            let delta = after
+                .iter()
+                .map(|(key, value)| {
+                    let before = before.get(key).unwrap_or(&0);
+                    (key.clone(), value.saturating_sub(*before))
+                })
+                .collect();
+            self.deltas.insert(name.clone(), delta);
+        }
+        Ok(())
+    }
+    /// Sum of a counter over the services.
+    fn total(&self, key: &str) -> u64 {
+        self.deltas.values().filter_map(|d| d.get(key)).sum()
+    }
+    /// Adds `<prefix>.<metric>` and `<prefix>.<service>.<metric>` metrics for
+    /// each (counter, metric) of `keys`, values divided by `unit`.
+    fn report(&self, tr: &mut TestReport, prefix: &str, keys: &[(&str, &str)], unit: f64) {
+        for (key, metric) in keys {
+            tr.metrics
+                .insert(format!("{prefix}.{metric}"), self.total(key) as f64 / unit);
+        }
+        for (name, delta) in &self.deltas {
+            for (key, metric) in keys {
+                let value = *delta.get(*key).unwrap_or(&0) as f64 / unit;
+                tr.metrics
+                    .insert(format!("{prefix}.{name}.{metric}"), value);
+            }
         }
     }
 }
 
 // ===
 
-pub struct SComponent {
-    value: u64,
-    services: Weak<RefCell<Vec<Service>>>,
+const CPU_KEYS: [(&str, &str); 2] = [("user_usec", "user"), ("system_usec", "system")];
+
+/// CPU time of the services cgroups during the test (`cpu.stat`).
+pub struct CpuComponent {
+    counters: CgroupCounters,
 }
 
-impl ETSComponent for SComponent {
+impl ETSComponent for CpuComponent {
+    fn name(&self) -> &str {
+        "cpu"
+    }
+    /// User + system CPU time, in seconds.
     fn value(&self) -> f64 {
-        self.to_octets() as f64
+        CPU_KEYS
+            .iter()
+            .map(|(k, _)| self.counters.total(k))
+            .sum::<u64>() as f64
+            / 1_000_000.0
     }
-    fn before_test(&mut self, _test: &dyn Test) {
-        self.value = 0;
+    fn before_test(&mut self, test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        self.counters.start(test)
     }
-    fn after_test(&mut self, test: &dyn Test) {
-        let services_rc = Weak::upgrade(&self.services).unwrap();
-        let services = services_rc.borrow();
+    fn after_test(&mut self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        self.counters.stop()
+    }
+    fn report(&self, tr: &mut TestReport) {
+        println!("      CpuComponent -> {} s", self.value());
+        self.counters.report(tr, "cpu", &CPU_KEYS, 1_000_000.0);
+    }
+}
 
-        for s in &*services {
-            if test.services_names().contains(&s.name) {
-                for p in &s.storage_paths {
-                    self.value += get_size(p).unwrap();
-                }
-            }
+impl CpuComponent {
+    pub fn new(services: &ServicesLink) -> Self {
+        Self {
+            counters: CgroupCounters::new(services, "cpu.stat", parse_keyed),
         }
     }
+    /// Directory holding one cgroup per `process_name`.
+    pub fn set_cgroup_root(&mut self, root: &str) {
+        self.counters.cgroup_root = root.into();
+    }
 }
 
-impl OctetsComponent for SComponent {
-    fn to_octets(&self) -> u64 {
-        self.value
+// ===
+
+const IO_KEYS: [(&str, &str); 4] = [
+    ("rbytes", "rbytes"),
+    ("wbytes", "wbytes"),
+    ("rios", "rios"),
+    ("wios", "wios"),
+];
+
+/// Disk I/O of the services cgroups during the test (`io.stat`).
+pub struct IoComponent {
+    counters: CgroupCounters,
+}
+
+impl ETSComponent for IoComponent {
+    fn name(&self) -> &str {
+        "io"
+    }
+    /// Octets read + written.
+    fn value(&self) -> f64 {
+        (self.counters.total("rbytes") + self.counters.total("wbytes")) as f64
+    }
+    fn before_test(&mut self, test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        self.counters.start(test)
+    }
+    fn after_test(&mut self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        self.counters.stop()
+    }
+    fn report(&self, tr: &mut TestReport) {
+        println!(
+            "      IoComponent -> {} Ko read, {} Ko written",
+            self.counters.total("rbytes") / 1024,
+            self.counters.total("wbytes") / 1024
+        );
+        self.counters.report(tr, "io", &IO_KEYS, 1.0);
     }
 }
 
-impl SComponent {
+impl IoComponent {
     pub fn new(services: &ServicesLink) -> Self {
         Self {
-            value: 0,
-            services: Rc::<RefCell<Vec<Service>>>::downgrade(services),
+            counters: CgroupCounters::new(services, "io.stat", parse_io_stat),
         }
     }
+    /// Directory holding one cgroup per `process_name`.
+    pub fn set_cgroup_root(&mut self, root: &str) {
+        self.counters.cgroup_root = root.into();
+    }
+}
+
+// ===
+
+/// How the numbers printed by the commands of a `CommandComponent` give its
+/// value.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Aggregation {
+    /// Sum printed after the test minus sum printed before (counters).
+    Delta,
+    /// Last number printed after the test (gauges).
+    Last,
+    /// Sum of the numbers printed after the test.
+    Sum,
+}
+
+/// Custom metric read from the standard output of commands run before and
+/// after each test.
+pub struct CommandComponent {
+    name: String,
+    unit: Option<String>,
+    aggregation: Aggregation,
+    before: Option<SystemCall>,
+    after: SystemCall,
+    before_values: Vec<f64>,
+    value: f64,
+}
+
+impl ETSComponent for CommandComponent {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+    fn value(&self) -> f64 {
+        self.value
+    }
+    fn before_test(&mut self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        self.value = 0.0;
+        self.before_values = match self.before {
+            Some(ref mut sc) => parse_numbers(&sc.output()?),
+            None => Vec::new(),
+        };
+        if self.aggregation == Aggregation::Delta && self.before_values.is_empty() {
+            bail!("No value printed before the test for metric {}", self.name);
+        }
+        Ok(())
+    }
+    fn after_test(&mut self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+        let values = parse_numbers(&self.after.output()?);
+        if values.is_empty() {
+            bail!("No value printed after the test for metric {}", self.name);
+        }
+        self.value = match self.aggregation {
+            Aggregation::Delta => {
+                values.iter().sum::<f64>() - self.before_values.iter().sum::<f64>()
+            }
+            Aggregation::Last => *values.last().unwrap(),
+            Aggregation::Sum => values.iter().sum(),
+        };
+        Ok(())
+    }
+}
+
+impl CommandComponent {
+    /// `before` is required by the `Delta` aggregation.
+    pub fn new(name: &str, before: Option<&str>, after: &str, aggregation: Aggregation) -> Self {
+        Self {
+            name: name.into(),
+            unit: None,
+            aggregation,
+            before: before.map(SystemCall::new),
+            after: SystemCall::new(after),
+            before_values: Vec::new(),
+            value: 0.0,
+        }
+    }
+    pub fn set_unit(&mut self, unit: &str) {
+        self.unit = Some(unit.into());
+    }
+}
+
+// Numbers found in a command output.
+fn parse_numbers(output: &str) -> Vec<f64> {
+    output
+        .split_whitespace()
+        .filter_map(|w| w.parse::<f64>().ok())
+        .filter(|v| v.is_finite())
+        .collect()
 }
 
 // -----------------------------------------------------------------------------
@@ -285,7 +868,10 @@ mod tests {
     use std::error::Error;
     use sysinfo::{System, SystemExt};
 
-    use crate::ets::system_call::SystemCall;
+    use crate::ets::energy_source::{ReplaySource, VjouleSource};
+    use crate::ets::packet_capture::{AfPacketCapture, PcapFileCapture};
+    use crate::ets::pcap::tests::{pcap_file, tcp_packet};
+    use crate::ets::pcap::LINKTYPE_RAW;
     use crate::ets::test::SystemCallTest;
 
     #[test]
@@ -306,7 +892,7 @@ mod tests {
         services.push(s);
 
         let link: ServicesLink = Rc::new(RefCell::new(services));
-        let mut ec = EComponent::new(&link);
+        let mut ec = EComponent::new(&link, Box::new(VjouleSource::default()));
 
         {
             let mut services = link.borrow_mut();
@@ -316,8 +902,8 @@ mod tests {
                 }
             }
         }
-        ec.before_campaign();
-        ec.before_test(&t);
+        ec.before_campaign()?;
+        ec.before_test(&t)?;
 
         use std::{thread, time};
         let mut sc = SystemCall::new("ps aux");
@@ -328,7 +914,7 @@ mod tests {
             thread::sleep(time::Duration::from_millis(100));
         }
 
-        ec.after_test(&t);
+        ec.after_test(&t)?;
         {
             let mut services = link.borrow_mut();
             for s in &mut *services {
@@ -341,7 +927,7 @@ mod tests {
         assert!(ec.to_joules() > 0.0);
         println!("E.value => {}", ec.value());
 
-        ec.after_campaign();
+        ec.after_campaign()?;
 
         Ok(())
     }
@@ -386,32 +972,44 @@ mod tests {
         t.add_service_name("Service 1");
 
         let link: ServicesLink = Rc::new(RefCell::new(services));
-        let mut tc = TComponent::new(&link);
+        let mut tc = TComponent::new(&link, Box::new(AfPacketCapture::new()));
 
         // Do tests requests
-        tc.before_test(&t);
+        tc.before_test(&t)?;
         let res = reqwest::blocking::get("http://localhost:8881/simple").unwrap();
         let body = res.text().unwrap();
         assert_eq!("0123456789", body);
         let res = reqwest::blocking::get("http://localhost:8882/double").unwrap();
         let body = res.text().unwrap();
         assert_eq!("01234567890123456789", body);
-        tc.after_test(&t);
+        tc.after_test(&t)?;
         let t1 = tc.to_octets();
         assert!(t1 > "0123456789".len() as u64);
 
-        tc.before_test(&t);
+        tc.before_test(&t)?;
         let res = reqwest::blocking::get("http://localhost:8881/simple").unwrap();
         let body = res.text().unwrap();
         assert_eq!("0123456789", body);
         let res = reqwest::blocking::get("http://localhost:8882/double").unwrap();
         let body = res.text().unwrap();
         assert_eq!("01234567890123456789", body);
-        tc.after_test(&t);
+        tc.after_test(&t)?;
         let t2 = tc.to_octets();
         assert!(t2 > "0123456789".len() as u64);
         assert_eq!(t1, t2);
 
+        // both requests and responses go through the service ports
+        let by_service = tc.to_octets_by_service();
+        let st = &by_service["Service 1"];
+        assert!(st.ingress > 0);
+        assert!(st.egress > 0);
+        assert_eq!(t2, st.ingress + st.egress);
+
+        let http = tc.to_protocols().http;
+        assert_eq!(2, http.requests);
+        assert_eq!(2, http.responses);
+        assert_eq!(("0123456789".len() * 3) as u64, http.body);
+
         // Stopping webserver
         sender2.send(()).unwrap();
         handle2.join().unwrap();
@@ -421,11 +1019,105 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn tcomponent_pcap_value() -> Result<(), Box<dyn Error>> {
+        let path = "/tmp/etsdiff_tcomponent_pcap.pcap";
+        let file = pcap_file(
+            LINKTYPE_RAW,
+            &[
+                (100, tcp_packet(50000, 8881)),
+                (1000, tcp_packet(8881, 50000)),
+                (60, tcp_packet(50001, 8882)),
+                (600, tcp_packet(8882, 50001)),
+                (500, tcp_packet(50002, 22)),
+            ],
+        );
+        std::fs::write(path, file)?;
+
+        let mut s1 = Service::new("Service 1");
+        s1.add_port(8881);
+        let mut s2 = Service::new("Service 2");
+        s2.add_port(8882);
+        let mut t = SystemCallTest::new("Test 1", "ls");
+        t.add_service_name("Service 1");
+        t.add_service_name("Service 2");
+
+        let link: ServicesLink = Rc::new(RefCell::new(vec![s1, s2]));
+        let mut tc = TComponent::new(&link, Box::new(PcapFileCapture::new(path)));
+
+        tc.before_test(&t)?;
+        tc.after_test(&t)?;
+        assert_eq!(1760, tc.to_octets());
+        let by_service = tc.to_octets_by_service();
+        assert_eq!(
+            ServiceTransfer {
+                ingress: 100,
+                egress: 1000
+            },
+            by_service["Service 1"]
+        );
+        assert_eq!(
+            ServiceTransfer {
+                ingress: 60,
+                egress: 600
+            },
+            by_service["Service 2"]
+        );
+
+        std::fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn ecomponent_replay_value() -> Result<(), Box<dyn Error>> {
+        let path = "/tmp/etsdiff_ecomponent_replay";
+        std::fs::create_dir_all(path)?;
+        std::fs::write(format!("{path}/p1"), "1.0\n3.5\n4.0\n7.0\n")?;
+        std::fs::write(format!("{path}/p2"), "10.0\n11.0\n")?;
+
+        let mut services: Vec<Service> = Vec::new();
+        let mut s = Service::new("Service 1");
+        s.set_process_name("p1");
+        services.push(s);
+        s = Service::new("Service 2");
+        s.set_process_name("p2");
+        services.push(s);
+        // without process there is nothing to measure
+        services.push(Service::new("Service 3"));
+
+        let mut t = SystemCallTest::new("Test 1", "ls");
+        t.add_service_name("Service 1");
+        t.add_service_name("Service 2");
+        t.add_service_name("Service 3");
+
+        let link: ServicesLink = Rc::new(RefCell::new(services));
+        let mut ec = EComponent::new(&link, Box::new(ReplaySource::new(path)));
+
+        ec.before_campaign()?;
+        ec.before_test(&t)?;
+        ec.after_test(&t)?;
+        assert_eq!(3.5, ec.to_joules());
+
+        ec.before_test(&t)?;
+        ec.after_test(&t)?;
+        assert_eq!(3.0, ec.to_joules());
+        let by_service = ec.to_joules_by_service();
+        assert_eq!(2, by_service.len());
+        assert_eq!(3.0, by_service["Service 1"]);
+        assert_eq!(0.0, by_service["Service 2"]);
+        ec.after_campaign()?;
+
+        std::fs::remove_dir_all(path)?;
+
+        Ok(())
+    }
+
     #[test]
     fn scomponent_min_iteration() {
         let services: Vec<Service> = Vec::new();
         let link: ServicesLink = Rc::new(RefCell::new(services));
-        let sc = SComponent::new(&link);
+        let sc = SComponent::new(&link, StorageMode::Apparent);
 
         assert_eq!(1, sc.min_iteration());
     }
@@ -469,9 +1161,9 @@ mod tests {
         assert_eq!(3, services.len());
 
         let link: ServicesLink = Rc::new(RefCell::new(services));
-        let mut sc = SComponent::new(&link);
-        sc.before_test(&test);
-        sc.after_test(&test);
+        let mut sc = SComponent::new(&link, StorageMode::Apparent);
+        sc.before_test(&test)?;
+        sc.after_test(&test)?;
 
         assert_eq!(10, sc.to_octets());
         assert_eq!(10.0, sc.value());
@@ -482,4 +1174,318 @@ mod tests {
 
         Ok(())
     }
+
+    // Services and test of `scomponent_value` with their files under `root`:
+    // 10 octets in 5 files.
+    fn storage_fixture(root: &str) -> Result<(ServicesLink, SystemCallTest), Box<dyn Error>> {
+        let _ = std::fs::remove_dir_all(root);
+        std::fs::create_dir_all(format!("{root}/test2"))?;
+        for (file, size) in [
+            ("test1.size", 1),
+            ("test2/1.size", 2),
+            ("test2/2.size", 3),
+            ("test3_1.size", 1),
+            ("test3_2.size", 3),
+        ] {
+            std::fs::File::create(format!("{root}/{file}"))?.set_len(size)?;
+        }
+
+        let mut services: Vec<Service> = Vec::new();
+        let mut s = Service::new("Service 1");
+        s.add_storage_path(&format!("{root}/test1.size"));
+        services.push(s);
+        s = Service::new("Service 2");
+        s.add_storage_path(&format!("{root}/test2/"));
+        services.push(s);
+        s = Service::new("Service 3");
+        s.add_storage_path(&format!("{root}/test3_1.size"));
+        s.add_storage_path(&format!("{root}/test3_2.size"));
+        services.push(s);
+
+        let mut test = SystemCallTest::new("Test 1", "ls");
+        test.add_service_name("Service 1");
+        test.add_service_name("Service 2");
+        test.add_service_name("Service 3");
+
+        Ok((Rc::new(RefCell::new(services)), test))
+    }
+
+    #[test]
+    fn scomponent_changes() -> Result<(), Box<dyn Error>> {
+        let root = "/tmp/etsdiff_scomponent_changes";
+        let (link, test) = storage_fixture(root)?;
+        let mut sc = SComponent::new(&link, StorageMode::Apparent);
+        sc.before_test(&test)?;
+        sc.after_test(&test)?;
+
+        assert_eq!(10, sc.apparent());
+        assert_eq!(StorageChanges::default(), sc.changes());
+
+        // test writing in the storage paths
+        sc.before_test(&test)?;
+        std::fs::File::create(format!("{root}/test2/3.size"))?.set_len(5)?;
+        std::fs::remove_file(format!("{root}/test3_1.size"))?;
+        sc.after_test(&test)?;
+
+        assert_eq!(14, sc.to_octets());
+        assert_eq!(
+            StorageChanges {
+                delta: 4,
+                created: 1,
+                modified: 0,
+                deleted: 1
+            },
+            sc.changes()
+        );
+
+        std::fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn scomponent_allocated() -> Result<(), Box<dyn Error>> {
+        let root = "/tmp/etsdiff_scomponent_allocated";
+        let (link, test) = storage_fixture(root)?;
+        let mut sc = SComponent::new(&link, StorageMode::Allocated);
+        sc.before_test(&test)?;
+        sc.after_test(&test)?;
+
+        // sparse files: nothing written, no block allocated
+        assert_eq!(StorageMode::Allocated, sc.mode());
+        assert_eq!(10, sc.apparent());
+        assert_eq!(0, sc.to_octets());
+
+        std::fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn scomponent_probes() -> Result<(), Box<dyn Error>> {
+        let root = "/tmp/etsdiff_scomponent_probes";
+        let (link, test) = storage_fixture(root)?;
+        let mut sc = SComponent::new(&link, StorageMode::Apparent);
+        link.borrow_mut()[2].add_storage_probe("echo 1000 octets");
+        sc.before_test(&test)?;
+        link.borrow_mut()[2].storage_probes[0] = SystemCall::new("echo 1500 octets");
+        sc.after_test(&test)?;
+
+        assert_eq!(1510, sc.to_octets());
+        assert_eq!(500, sc.changes().delta);
+        let by_service = sc.to_octets_by_service();
+        assert_eq!(3, by_service.len());
+        assert_eq!(
+            ServiceStorage {
+                paths: 5,
+                probes: 0,
+                files: Vec::new()
+            },
+            by_service["Service 2"]
+        );
+        assert_eq!(
+            ServiceStorage {
+                paths: 4,
+                probes: 1500,
+                files: Vec::new()
+            },
+            by_service["Service 3"]
+        );
+
+        std::fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn scomponent_filter() -> Result<(), Box<dyn Error>> {
+        let root = "/tmp/etsdiff_scomponent_filter";
+        let (link, test) = storage_fixture(root)?;
+        link.borrow_mut()[1].add_storage_exclude("2.*")?;
+        let mut sc = SComponent::new(&link, StorageMode::Apparent);
+        sc.before_test(&test)?;
+        sc.after_test(&test)?;
+
+        assert_eq!(7, sc.to_octets());
+        let by_service = sc.to_octets_by_service();
+        assert_eq!(
+            vec![format!("{root}/test2/1.size")],
+            by_service["Service 2"].files
+        );
+        assert!(by_service["Service 3"].files.is_empty());
+
+        std::fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn mcomponent_value() -> Result<(), Box<dyn Error>> {
+        // fake cgroup v2 files
+        let root = "/tmp/etsdiff_mcomponent";
+        let cgroup = format!("{root}/pfake");
+        let _ = std::fs::remove_dir_all(root);
+        std::fs::create_dir_all(&cgroup)?;
+        std::fs::write(format!("{cgroup}/memory.current"), "4096\n")?;
+        std::fs::write(
+            format!("{cgroup}/memory.stat"),
+            "anon 1000\nfile_mapped 24\n",
+        )?;
+
+        let mut services: Vec<Service> = Vec::new();
+        let mut s = Service::new("Service 1");
+        s.set_process_name("pfake");
+        services.push(s);
+        services.push(Service::new("Service 2"));
+
+        let mut test = SystemCallTest::new("Test 1", "ls");
+        test.add_service_name("Service 1");
+        test.add_service_name("Service 2");
+
+        let link: ServicesLink = Rc::new(RefCell::new(services));
+        let mut mc = MComponent::new(&link);
+        mc.set_cgroup_root(root);
+
+        mc.before_test(&test)?;
+        std::thread::sleep(Duration::from_millis(250));
+        std::fs::write(format!("{cgroup}/memory.current"), "8192\n")?;
+        std::fs::write(
+            format!("{cgroup}/memory.stat"),
+            "anon 3000\nfile_mapped 24\n",
+        )?;
+        mc.after_test(&test)?;
+
+        // no memory.peak: highest sampled memory.current
+        let memory = &mc.memory_by_service()["Service 1"];
+        assert_eq!(1, mc.memory_by_service().len());
+        assert_eq!(8192, memory.peak);
+        assert!(memory.average_rss > 1024 && memory.average_rss < 3024);
+        assert_eq!(8192.0, mc.value());
+
+        let mut tr = TestReport::new("Test 1");
+        mc.report(&mut tr);
+        assert_eq!(8192.0, tr.metrics["memory.peak"]);
+        assert_eq!(8192.0, tr.metrics["memory.Service 1.peak"]);
+        assert_eq!(4, tr.metrics.len());
+
+        // no cgroup v2
+        std::fs::remove_dir_all(root)?;
+        assert!(mc.before_test(&test).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cpu_io_components_value() -> Result<(), Box<dyn Error>> {
+        // fake cgroup v2 files
+        let root = "/tmp/etsdiff_cpu_io_components";
+        let cgroup = format!("{root}/pfake");
+        let _ = std::fs::remove_dir_all(root);
+        std::fs::create_dir_all(&cgroup)?;
+        std::fs::write(
+            format!("{cgroup}/cpu.stat"),
+            "usage_usec 3000000\nuser_usec 2000000\nsystem_usec 1000000\n",
+        )?;
+        std::fs::write(
+            format!("{cgroup}/io.stat"),
+            "8:0 rbytes=4096 wbytes=0 rios=1 wios=0 dbytes=0 dios=0\n",
+        )?;
+
+        let mut services: Vec<Service> = Vec::new();
+        let mut s = Service::new("Service 1");
+        s.set_process_name("pfake");
+        services.push(s);
+
+        let mut test = SystemCallTest::new("Test 1", "ls");
+        test.add_service_name("Service 1");
+
+        let link: ServicesLink = Rc::new(RefCell::new(services));
+        let mut cc = CpuComponent::new(&link);
+        cc.set_cgroup_root(root);
+        let mut ic = IoComponent::new(&link);
+        ic.set_cgroup_root(root);
+
+        cc.before_test(&test)?;
+        ic.before_test(&test)?;
+        std::fs::write(
+            format!("{cgroup}/cpu.stat"),
+            "usage_usec 4500000\nuser_usec 3000000\nsystem_usec 1500000\n",
+        )?;
+        std::fs::write(
+            format!("{cgroup}/io.stat"),
+            "8:0 rbytes=8192 wbytes=2048 rios=2 wios=1 dbytes=0 dios=0\n\
+             8:16 rbytes=0 wbytes=1024 rios=0 wios=1 dbytes=0 dios=0\n",
+        )?;
+        cc.after_test(&test)?;
+        ic.after_test(&test)?;
+
+        assert_eq!(1.5, cc.value());
+        assert_eq!(7168.0, ic.value());
+
+        let mut tr = TestReport::new("Test 1");
+        cc.report(&mut tr);
+        ic.report(&mut tr);
+        assert_eq!(1.0, tr.metrics["cpu.user"]);
+        assert_eq!(0.5, tr.metrics["cpu.Service 1.system"]);
+        assert_eq!(4096.0, tr.metrics["io.rbytes"]);
+        assert_eq!(3072.0, tr.metrics["io.Service 1.wbytes"]);
+        assert_eq!(2.0, tr.metrics["io.wios"]);
+        assert_eq!(4 + 8, tr.metrics.len());
+
+        // io controller not enabled
+        std::fs::remove_file(format!("{cgroup}/io.stat"))?;
+        assert!(ic.before_test(&test).is_err());
+
+        std::fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn command_component_value() -> Result<(), Box<dyn Error>> {
+        let test = SystemCallTest::new("Test 1", "ls");
+
+        let counter = "/tmp/etsdiff_command_component";
+        std::fs::write(counter, "requests 100\n")?;
+        let mut cc = CommandComponent::new(
+            "requests",
+            Some(&format!("cat {counter}")),
+            &format!("cat {counter}"),
+            Aggregation::Delta,
+        );
+        cc.set_unit("requests");
+        assert_eq!("requests", cc.name());
+        assert_eq!(Some("requests"), cc.unit());
+
+        cc.before_test(&test)?;
+        std::fs::write(counter, "requests 142\n")?;
+        cc.after_test(&test)?;
+        assert_eq!(42.0, cc.value());
+
+        let mut tr = TestReport::new("Test 1");
+        cc.report(&mut tr);
+        assert_eq!(42.0, tr.metrics["requests"]);
+
+        std::fs::write(counter, "1.5\n2.5\n4\n")?;
+        cc = CommandComponent::new("gauge", None, &format!("cat {counter}"), Aggregation::Last);
+        cc.before_test(&test)?;
+        cc.after_test(&test)?;
+        assert_eq!(4.0, cc.value());
+
+        cc = CommandComponent::new("watts", None, &format!("cat {counter}"), Aggregation::Sum);
+        cc.before_test(&test)?;
+        cc.after_test(&test)?;
+        assert_eq!(8.0, cc.value());
+
+        // no value
+        cc = CommandComponent::new("none", None, "echo none", Aggregation::Last);
+        cc.before_test(&test)?;
+        assert!(cc.after_test(&test).is_err());
+        cc = CommandComponent::new("delta", None, "echo 1", Aggregation::Delta);
+        assert!(cc.before_test(&test).is_err());
+
+        std::fs::remove_file(counter)?;
+
+        Ok(())
+    }
 }
@@ -4,21 +4,41 @@
 use std::cell::RefCell;
 use std::error::Error;
 use std::rc::Rc;
-
-use super::etscomponent::{EComponent, ETSComponent, OctetsComponent, SComponent, TComponent};
-use super::iteration_scheduler::{IterationScheduler, SchedulerType, StageredScheduler};
-use super::report::{Report, TestReport};
+use std::time::{Duration, Instant};
+
+use super::cooldown::Cooldown;
+use super::energy_source::{
+    EnergySource, EnergySourceType, RaplSource, ReplaySource, VjouleSource, VJOULE_DEFAULT_TIMEOUT,
+};
+use super::etscomponent::{
+    CpuComponent, EComponent, ETSComponent, IoComponent, MComponent, SComponent, TComponent,
+};
+use super::iteration_scheduler::{
+    AdaptiveScheduler, BlockedScheduler, IterationScheduler, LatinSquareScheduler,
+    RandomizedScheduler, SchedulerType, StageredScheduler,
+};
+use super::packet_capture::{
+    AfPacketCapture, CaptureSettings, CaptureType, PacketCapture, PcapFileCapture, TSharkCapture,
+};
+use super::report::{BaselineReport, FunctionalUnits, Report, Schedule, TestReport};
 use super::service::ServicesLink;
+use super::storage::StorageMode;
 use super::test::Test;
 
 pub struct ETSdiff {
     pub services: ServicesLink,
     pub tests: Vec<Box<dyn Test>>,
-    pub e_component: Option<EComponent>,
-    pub s_component: Option<SComponent>,
-    pub t_component: Option<TComponent>,
+    /// Started in order and stopped in reverse order.
+    pub components: Vec<Box<dyn ETSComponent>>,
     pub scheduler: Option<Box<dyn IterationScheduler>>,
+    pub baseline_duration: Option<Duration>,
+    /// Warm-up runs of each test, before the iterations.
+    pub warmup: u32,
+    /// Before each run.
+    pub cooldown: Option<Cooldown>,
     pub report: Report,
+    // campaign start, origin of the iterations timestamps
+    started: Instant,
 }
 
 impl ETSdiff {
@@ -26,29 +46,97 @@ impl ETSdiff {
         let mut ret = Self {
             services: Rc::new(RefCell::new(Vec::new())),
             tests: Vec::new(),
-            e_component: None,
-            s_component: None,
-            t_component: None,
+            components: Vec::new(),
             scheduler: None,
+            baseline_duration: None,
+            warmup: 0,
+            cooldown: None,
             report: Report::new(),
+            started: Instant::now(),
         };
-        ret.set_s_component();
-        ret.set_t_component();
-        ret.set_e_component();
+        ret.set_t_component(CaptureType::AfPacket, CaptureSettings::localhost());
+        ret.set_s_component(StorageMode::Apparent);
+        ret.set_e_component(EnergySourceType::Vjoule(VJOULE_DEFAULT_TIMEOUT));
 
         ret
     }
 
-    pub fn set_s_component(&mut self) {
-        self.s_component = Some(SComponent::new(&self.services));
+    /// Replaces the component with the same name. A new component is added
+    /// before the energy component, energy being measured the closest to the
+    /// test.
+    pub fn set_component(&mut self, component: Box<dyn ETSComponent>) {
+        let position = |name: &str| self.components.iter().position(|c| c.name() == name);
+        match position(component.name()) {
+            Some(i) => self.components[i] = component,
+            None => match position("energy") {
+                Some(i) => self.components.insert(i, component),
+                None => self.components.push(component),
+            },
+        }
+    }
+
+    pub fn component(&self, name: &str) -> Option<&dyn ETSComponent> {
+        self.components
+            .iter()
+            .find(|c| c.name() == name)
+            .map(|c| c.as_ref())
+    }
+
+    pub fn component_names(&self) -> Vec<&str> {
+        self.components.iter().map(|c| c.name()).collect()
+    }
+
+    /// Keeps only the named components, turning the others off. Optional
+    /// components (`memory`, `cpu`, `io`) are added.
+    pub fn enable_components(&mut self, names: &[String]) -> Result<(), Box<dyn Error>> {
+        let mut added = Vec::new();
+        for name in names {
+            if self.component(name).is_none() {
+                match self.optional_component(name) {
+                    Some(component) => added.push(component),
+                    None => bail!("Unknown ETSComponent {}", name),
+                }
+            }
+        }
+        for component in added {
+            self.set_component(component);
+        }
+        self.components
+            .retain(|c| names.iter().any(|name| name == c.name()));
+        Ok(())
+    }
+
+    fn optional_component(&self, name: &str) -> Option<Box<dyn ETSComponent>> {
+        match name {
+            "memory" => Some(Box::new(MComponent::new(&self.services))),
+            "cpu" => Some(Box::new(CpuComponent::new(&self.services))),
+            "io" => Some(Box::new(IoComponent::new(&self.services))),
+            _ => None,
+        }
     }
 
-    pub fn set_t_component(&mut self) {
-        self.t_component = Some(TComponent::new(&self.services));
+    pub fn set_s_component(&mut self, mode: StorageMode) {
+        self.set_component(Box::new(SComponent::new(&self.services, mode)));
     }
 
-    pub fn set_e_component(&mut self) {
-        self.e_component = Some(EComponent::new(&self.services));
+    pub fn set_t_component(&mut self, ct: CaptureType, settings: CaptureSettings) {
+        let capture: Box<dyn PacketCapture> = match ct {
+            CaptureType::AfPacket => Box::new(AfPacketCapture::new()),
+            CaptureType::TShark => Box::new(TSharkCapture::new()),
+            CaptureType::Pcap(path) => Box::new(PcapFileCapture::new(&path)),
+        };
+        let mut component = TComponent::new(&self.services, capture);
+        component.set_settings(settings);
+        self.set_component(Box::new(component));
+    }
+
+    pub fn set_e_component(&mut self, st: EnergySourceType) {
+        let source: Box<dyn EnergySource> = match st {
+            EnergySourceType::Vjoule(timeout) => Box::new(VjouleSource::new(timeout)),
+            EnergySourceType::Rapl => Box::new(RaplSource::new()),
+            EnergySourceType::Replay(path) => Box::new(ReplaySource::new(&path)),
+        };
+        self.set_component(Box::new(EComponent::new(&self.services, source)));
     }
 
     pub fn set_scheduler(&mut self, st: SchedulerType, nb_iteration: u32) {
@@ -56,9 +144,41 @@ impl ETSdiff {
             SchedulerType::StageredScheduler => {
                 Some(Box::new(StageredScheduler::new(nb_iteration)))
             }
+            SchedulerType::BlockedScheduler => Some(Box::new(BlockedScheduler::new(nb_iteration))),
+            SchedulerType::LatinSquareScheduler => {
+                Some(Box::new(LatinSquareScheduler::new(nb_iteration)))
+            }
+            SchedulerType::RandomizedScheduler(seed) => Some(Box::new(RandomizedScheduler::new(
+                nb_iteration,
+                seed.unwrap_or_else(rand::random),
+            ))),
+            SchedulerType::AdaptiveScheduler {
+                min_iteration,
+                target_width,
+                time_budget,
+            } => {
+                let mut scheduler =
+                    AdaptiveScheduler::new(min_iteration, nb_iteration, target_width);
+                if let Some(time_budget) = time_budget {
+                    scheduler.set_time_budget(time_budget);
+                }
+                Some(Box::new(scheduler))
+            }
         }
     }
 
+    pub fn set_baseline(&mut self, duration: Duration) {
+        self.baseline_duration = Some(duration);
+    }
+
+    pub fn set_warmup(&mut self, warmup: u32) {
+        self.warmup = warmup;
+    }
+
+    pub fn set_cooldown(&mut self, cooldown: Cooldown) {
+        self.cooldown = Some(cooldown);
+    }
+
     pub fn get_ordered_tests_list(&mut self) -> Vec<u32> {
         if self.scheduler.is_none() {
             self.set_scheduler(SchedulerType::StageredScheduler, 2);
@@ -69,75 +189,82 @@ impl ETSdiff {
             .get_ordered_list(self.tests.len().try_into().unwrap())
     }
 
-    fn prepare_etscomponents(&mut self) {
+    fn prepare_etscomponents(&mut self) -> Result<(), Box<dyn Error>> {
         println!("Preparing ETSComponents...");
-        match self.t_component {
-            None => println!("  No TComponent"),
-            Some(ref mut c) => {
-                println!("  Preparing TComponent");
-                c.before_campaign();
-            }
+        if self.components.is_empty() {
+            println!("  No ETSComponent");
         }
-        match self.s_component {
-            None => println!("  No SComponent"),
-            Some(ref mut c) => {
-                println!("  Preparing SComponent");
-                c.before_campaign();
+        for c in &mut self.components {
+            println!("  Preparing {}", c.name());
+            if let Some(unit) = c.unit() {
+                self.report.set_unit(c.name(), unit);
             }
-        }
-        match self.e_component {
-            None => println!("  No EComponent"),
-            Some(ref mut c) => {
-                println!("  Preparing EComponent");
-                c.before_campaign();
+            c.before_campaign()?;
+            for test in &self.tests {
+                c.validate(test.as_ref())?;
             }
         }
+        Ok(())
     }
-    fn release_etscomponents(&mut self) {
+    // All components are released, even if one fails, the first error is returned.
+    fn release_etscomponents(&mut self) -> Result<(), Box<dyn Error>> {
         println!("Releasing ETSComponents...");
-        match self.t_component {
-            None => println!("  No TComponent"),
-            Some(ref mut c) => {
-                println!("  Releasing TComponent");
-                c.after_campaign();
-            }
+        let mut results = Vec::new();
+        for c in &mut self.components {
+            println!("  Releasing {}", c.name());
+            results.push(c.after_campaign());
         }
-        match self.s_component {
-            None => println!("  No SComponent"),
-            Some(ref mut c) => {
-                println!("  Releasing SComponent");
-                c.after_campaign();
+        results.into_iter().collect()
+    }
+
+    // On error, the components already started are stopped (no capture or
+    // sampling thread left running).
+    fn start_etscomponents(&mut self, itest: usize) -> Result<(), Box<dyn Error>> {
+        let test = self.tests[itest].as_ref();
+        for i in 0..self.components.len() {
+            println!("      Starting {}", self.components[i].name());
+            if let Err(err) = self.components[i].before_test(test) {
+                let _ = Self::stop_started_etscomponents(&mut self.components[..i], test);
+                return Err(err);
             }
         }
-        match self.e_component {
-            None => println!("  No EComponent"),
-            Some(ref mut c) => {
-                println!("  Realeasing EComponent");
-                c.after_campaign();
-            }
+        Ok(())
+    }
+    fn stop_etscomponents(&mut self, itest: usize) -> Result<(), Box<dyn Error>> {
+        Self::stop_started_etscomponents(&mut self.components, self.tests[itest].as_ref())
+    }
+    // All components are stopped in reverse order, even if one fails, the first
+    // error is returned.
+    fn stop_started_etscomponents(
+        components: &mut [Box<dyn ETSComponent>],
+        test: &dyn Test,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut results = Vec::new();
+        for c in components.iter_mut().rev() {
+            println!("      Stoping {}", c.name());
+            results.push(c.after_test(test));
         }
+        results.into_iter().collect()
     }
 
-    /*
-    fn prepare_services(&mut self, test: &dyn Test) {
-        let mut services = self.services.borrow_mut();
-        println!("Prepare {} services...", services.len());
+    fn prepare_services(services: &ServicesLink, test: &dyn Test) {
+        let mut services = services.borrow_mut();
+        println!("    Prepare services...");
         for s in &mut *services {
             if test.services_names().contains(&s.name) {
                 if s.prepare.is_none() {
-                    println!("  Service {} don't have prepare method", s.name);
+                    println!("      Service {} don't have prepare method", s.name);
                 } else if s.prepare().is_err() {
-                    eprintln!("  Error when preparing service {}", s.name);
+                    eprintln!("      Error when preparing service {}", s.name);
                 } else {
-                    println!("  Service {} prepare()", s.name);
+                    println!("      Service {} prepare()", s.name);
                 }
             }
         }
-        println!("--\n");
     }
-    fn clean_services(&mut self, test: &dyn Test) {
-        let mut services = self.services.borrow_mut();
-        println!("    Clean {} services...", services.len());
+    fn clean_and_release_services(services: &ServicesLink, test: &dyn Test) {
+        let mut services = services.borrow_mut();
+        println!("    Clean and Release services...");
         for s in &mut *services {
             if test.services_names().contains(&s.name) {
                 if s.clean.is_none() {
@@ -147,170 +274,224 @@ impl ETSdiff {
                 } else {
                     println!("      Service {} clean()", s.name);
                 }
-            }
-        }
-        println!("--\n");
-    }
-    fn release_services(&mut self, test: &dyn Test) {
-        let mut services = self.services.borrow_mut();
-        println!("Release {} services...", services.len());
-        for s in &mut *services {
-            if test.services_names().contains(&s.name) {
+
                 if s.release.is_none() {
-                    println!("  Service {} don't have release method", s.name);
+                    println!("      Service {} don't have release method", s.name);
                 } else if s.release().is_err() {
-                    eprintln!("  Error when releasing service {}", s.name);
+                    eprintln!("      Error when releasing service {}", s.name);
                 } else {
-                    println!("  Service {} release()", s.name);
+                    println!("      Service {} release()", s.name);
                 }
             }
         }
-        println!("--\n");
     }
-    */
+
+    // Idle measurement of the test services, without running the test.
+    fn measure_baseline(
+        &mut self,
+        itest: usize,
+        duration: Duration,
+    ) -> Result<BaselineReport, Box<dyn Error>> {
+        let test = self.tests[itest].as_ref();
+        println!("  [BASELINE: {}]", test.name());
+
+        Self::prepare_services(&self.services, test);
+        let br = self.baseline_iteration(itest, duration);
+        Self::clean_and_release_services(&self.services, self.tests[itest].as_ref());
+
+        br
+    }
+    fn baseline_iteration(
+        &mut self,
+        itest: usize,
+        duration: Duration,
+    ) -> Result<BaselineReport, Box<dyn Error>> {
+        let test = self.tests[itest].as_ref();
+        let mut br = BaselineReport::new(test.name());
+
+        println!("    Starting ETSComponents...");
+        self.start_etscomponents(itest)?;
+
+        println!("    => Idle for {} s...", duration.as_secs_f64());
+        let start = Instant::now();
+        std::thread::sleep(duration);
+        br.duration = start.elapsed().as_secs_f64();
+
+        println!("    Stoping ETSComponents...");
+        self.stop_etscomponents(itest)?;
+        for c in &self.components {
+            c.report_baseline(&mut br);
+        }
+        println!(
+            "    Baseline -> {} Joules, {} Ko, {} Ko",
+            br.energy,
+            br.transfer / 1024,
+            br.storage_drift / 1024
+        );
+
+        Ok(br)
+    }
 
     pub fn execute(&mut self) -> Result<(), Box<dyn Error>> {
         println!("Nb tests: {:?}", self.tests.len());
+        self.started = Instant::now();
 
-        let tests_order = self.get_ordered_tests_list();
-        println!("Ordered test: {tests_order:?}");
+        if self.scheduler.is_none() {
+            self.set_scheduler(SchedulerType::StageredScheduler, 2);
+        }
+        let seed = self.scheduler.as_ref().unwrap().seed();
+        if let Some(seed) = seed {
+            println!("Seed: {seed}");
+        }
         println!("--\n");
 
-        self.prepare_etscomponents();
+        // on error the campaign is aborted, but components are still released
+        let mut result = self.prepare_etscomponents();
         println!("--\n");
 
-        println!("Iterations:");
-        for itest in tests_order {
-            let test = &mut self.tests[itest as usize];
-            let mut tr = TestReport::new(test.name());
-            println!("  [TEST: {}]", test.name());
-
-            {
-                // self.prepare_services(test.as_ref()); TODO: undersant how to deal with borrow *self more than once
-                let mut services = self.services.borrow_mut();
-                println!("    Prepare services...");
-                for s in &mut *services {
-                    if test.services_names().contains(&s.name) {
-                        if s.prepare.is_none() {
-                            println!("      Service {} don't have prepare method", s.name);
-                        } else if s.prepare().is_err() {
-                            eprintln!("      Error when preparing service {}", s.name);
-                        } else {
-                            println!("      Service {} prepare()", s.name);
-                        }
-                    }
-                }
-            }
+        if result.is_ok() {
+            result = self.run_campaign();
+            println!("--\n");
+        }
 
-            println!("    Starting ETSComponents...");
-            match self.t_component {
-                None => println!("      No TComponent to start"),
-                Some(ref mut c) => {
-                    println!("      Starting TComponent");
-                    c.before_test(test.as_ref());
-                }
-            }
-            match self.s_component {
-                None => println!("      No SComponent to start"),
-                Some(ref mut c) => {
-                    println!("      Starting SComponent");
-                    c.before_test(test.as_ref());
-                }
-            }
-            match self.e_component {
-                None => println!("      No EComponent to start"),
-                Some(ref mut c) => {
-                    println!("      Starting EComponent");
-                    c.before_test(test.as_ref());
-                }
-            }
+        // the order actually run
+        let order: Vec<String> = self
+            .report
+            .details()
+            .filter(|tr| !tr.warmup)
+            .map(|tr| tr.name().into())
+            .collect();
+        println!("Tests order: {order:?}");
+        self.report.set_schedule(Schedule { seed, order });
 
-            println!("    => Running test...");
-            test.run()?;
+        let released = self.release_etscomponents();
+        result?;
+        released?;
 
-            println!("    Stoping ETSComponents...");
-            match self.e_component {
-                None => println!("      No EComponent to stop"),
-                Some(ref mut c) => {
-                    println!("      Stoping EComponent");
-                    c.after_test(test.as_ref());
-                }
-            }
-            match self.t_component {
-                None => println!("      No TComponent to stop"),
-                Some(ref mut c) => {
-                    println!("      Stoping TComponent");
-                    c.after_test(test.as_ref());
-                }
-            }
-            match self.s_component {
-                None => println!("      No SComponent to stop"),
-                Some(ref mut c) => {
-                    println!("      Stoping SComponent");
-                    c.after_test(test.as_ref());
-                }
-            }
+        // report
+        println!("Finalizing report");
+        self.report.compute_total();
 
-            println!("    Get results...");
-            match self.e_component {
-                None => println!("      No EComponent"),
-                Some(ref mut c) => {
-                    println!("      EComponent -> {} Joules", c.to_joules());
-                    tr.energy = c.to_joules();
-                }
-            }
-            match self.t_component {
-                None => println!("      No TComponent"),
-                Some(ref mut c) => {
-                    println!("      TComponent -> {} Ko", c.to_octets() / 1024);
-                    tr.transfer = c.to_octets();
-                }
+        Ok(())
+    }
+
+    fn run_campaign(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(duration) = self.baseline_duration {
+            println!("Baseline:");
+            for itest in 0..self.tests.len() {
+                let br = self.measure_baseline(itest, duration)?;
+                self.report.add_baseline_report(br);
             }
-            match self.s_component {
-                None => println!("      No SComponent"),
-                Some(ref mut c) => {
-                    println!("      SComponent -> {} Ko", c.to_octets() / 1024);
-                    tr.storage = c.to_octets();
+            println!("--\n");
+        }
+
+        let warmups: Vec<u32> = self
+            .tests
+            .iter()
+            .map(|t| t.warmup().unwrap_or(self.warmup))
+            .collect();
+        if warmups.iter().any(|n| *n > 0) {
+            println!("Warm-up:");
+            for (itest, n) in warmups.into_iter().enumerate() {
+                for _i in 0..n {
+                    self.run_test(itest, true)?;
                 }
             }
-            self.report.add_test_report(tr);
-
-            {
-                // self.clean_services(test.as_ref()); TODO: undersant how to deal with borrow *self more than once
-                // self.release_services(test.as_ref()); TODO: undersant how to deal with borrow *self more than once
-                let mut services = self.services.borrow_mut();
-                println!("    Clean and Release services...");
-                for s in &mut *services {
-                    if test.services_names().contains(&s.name) {
-                        if s.clean.is_none() {
-                            println!("      Service {} don't have clean method", s.name);
-                        } else if s.clean().is_err() {
-                            eprintln!("      Error when cleaning service {}", s.name);
-                        } else {
-                            println!("      Service {} clean()", s.name);
-                        }
-
-                        if s.release.is_none() {
-                            println!("      Service {} don't have release method", s.name);
-                        } else if s.release().is_err() {
-                            eprintln!("      Error when releasing service {}", s.name);
-                        } else {
-                            println!("      Service {} release()", s.name);
-                        }
-                    }
-                }
+            println!("--\n");
+        }
+
+        println!("Iterations:");
+        let nb_tests = self.tests.len() as u32;
+        // the scheduler picks each test from the reports of the previous ones
+        loop {
+            let done: Vec<&TestReport> = self.report.details().filter(|tr| !tr.warmup).collect();
+            let next =
+                self.scheduler
+                    .as_ref()
+                    .unwrap()
+                    .next_test(nb_tests, &done, self.started.elapsed());
+            match next {
+                Some(itest) => self.run_test(itest as usize, false)?,
+                None => break,
             }
         }
-        println!("--\n");
 
-        self.release_etscomponents();
+        Ok(())
+    }
 
-        // report
-        println!("Finalizing report");
-        self.report.compute_total();
+    // Full cycle of one run: services prepared, test measured, services cleaned.
+    fn run_test(&mut self, itest: usize, warmup: bool) -> Result<(), Box<dyn Error>> {
+        let test = self.tests[itest].as_ref();
+        println!("  [TEST: {}]", test.name());
 
+        Self::prepare_services(&self.services, test);
+
+        // the services start-up is part of what the system cools down from
+        let conditions = self.cooldown.as_ref().map(|cooldown| {
+            println!("    Cooling down...");
+            let conditions = cooldown.wait();
+            println!("      {conditions:?}");
+            conditions
+        });
+
+        let tr = self.run_iteration(itest);
+        Self::clean_and_release_services(&self.services, self.tests[itest].as_ref());
+
+        let mut tr = tr?;
+        tr.warmup = warmup;
+        tr.pre_test = conditions;
+        self.report.add_test_report(tr);
         Ok(())
     }
+
+    fn run_iteration(&mut self, itest: usize) -> Result<TestReport, Box<dyn Error>> {
+        let mut tr = TestReport::new(self.tests[itest].name());
+
+        println!("    Starting ETSComponents...");
+        self.start_etscomponents(itest)?;
+
+        println!("    => Running test...");
+        let start = self.started.elapsed().as_secs_f64();
+        if let Err(err) = self.tests[itest].run() {
+            let _ = self.stop_etscomponents(itest);
+            return Err(err);
+        }
+        let end = self.started.elapsed().as_secs_f64();
+        let duration = end - start;
+        println!("    <= {duration} s");
+        tr.start = Some(start);
+        tr.end = Some(end);
+        tr.duration = Some(duration);
+
+        println!("    Stoping ETSComponents...");
+        self.stop_etscomponents(itest)?;
+
+        println!("    Get results...");
+        for c in &self.components {
+            c.report(&mut tr);
+        }
+        let test = self.tests[itest].as_ref();
+        if let Some(count) = test.functional_units() {
+            let fu = FunctionalUnits::new(count, &tr);
+            println!(
+                "      Per unit -> {} Joules, {} octets, {} units/s",
+                fu.energy_per_unit, fu.transfer_per_unit, fu.throughput
+            );
+            tr.functional_units = Some(fu);
+        }
+        if let Some(br) = self.report.baseline_report(test.name()) {
+            let corrected = br.correct(&tr, duration);
+            println!(
+                "      Baseline corrected -> {} Joules, {} Ko, {:?} octets stored",
+                corrected.energy,
+                corrected.transfer / 1024,
+                corrected.storage_delta
+            );
+            tr.baseline_corrected = Some(corrected);
+        }
+
+        Ok(tr)
+    }
 }
 
 impl Default for ETSdiff {
@@ -328,6 +509,30 @@ mod tests {
     use super::super::service::Service;
     use super::super::test::SystemCallTest;
 
+    // Records its starts and stops, can fail to start.
+    struct Probe {
+        name: String,
+        fail_start: bool,
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl ETSComponent for Probe {
+        fn name(&self) -> &str {
+            &self.name
+        }
+        fn before_test(&mut self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+            self.log.borrow_mut().push(format!("start {}", self.name));
+            if self.fail_start {
+                bail!("Can't start {}", self.name);
+            }
+            Ok(())
+        }
+        fn after_test(&mut self, _test: &dyn Test) -> Result<(), Box<dyn Error>> {
+            self.log.borrow_mut().push(format!("stop {}", self.name));
+            Ok(())
+        }
+    }
+
     #[test]
     fn etsdiff_creation() {
         let etsd = ETSdiff::new();
@@ -367,21 +572,250 @@ mod tests {
     fn etsdiff_set_e_component() {
         let etsd = ETSdiff::new();
 
-        assert!(etsd.e_component.is_some());
+        assert!(etsd.component("energy").is_some());
     }
 
     #[test]
     fn etsdiff_set_t_component() {
         let etsd = ETSdiff::new();
 
-        assert!(etsd.s_component.is_some());
+        assert!(etsd.component("transfer").is_some());
     }
 
     #[test]
     fn etsdiff_set_s_component() {
         let etsd = ETSdiff::new();
 
-        assert!(etsd.s_component.is_some());
+        assert!(etsd.component("storage").is_some());
+    }
+
+    #[test]
+    fn etsdiff_components() {
+        let mut etsd = ETSdiff::new();
+
+        assert_eq!(
+            vec!["transfer", "storage", "energy"],
+            etsd.component_names()
+        );
+
+        // replaced in place
+        etsd.set_e_component(EnergySourceType::Rapl);
+        etsd.set_s_component(StorageMode::Allocated);
+        assert_eq!(
+            vec!["transfer", "storage", "energy"],
+            etsd.component_names()
+        );
+
+        assert!(etsd
+            .enable_components(&["storage".into(), "unknown".into()])
+            .is_err());
+        assert_eq!(3, etsd.components.len());
+
+        assert!(etsd
+            .enable_components(&["transfer".into(), "storage".into()])
+            .is_ok());
+        assert_eq!(vec!["transfer", "storage"], etsd.component_names());
+
+        assert!(etsd
+            .enable_components(&["storage".into(), "memory".into(), "cpu".into(), "io".into()])
+            .is_ok());
+        assert_eq!(
+            vec!["storage", "memory", "cpu", "io"],
+            etsd.component_names()
+        );
+        assert!(etsd.component("energy").is_none());
+
+        // new components are added before energy
+        etsd.set_e_component(EnergySourceType::Rapl);
+        etsd.set_t_component(CaptureType::AfPacket, CaptureSettings::localhost());
+        assert_eq!(
+            vec!["storage", "memory", "cpu", "io", "transfer", "energy"],
+            etsd.component_names()
+        );
+    }
+
+    #[test]
+    fn etsdiff_execute_without_component() -> Result<(), Box<dyn Error>> {
+        let mut etsd = ETSdiff::new();
+        etsd.enable_components(&[])?;
+        etsd.tests
+            .push(Box::new(SystemCallTest::new("Test 1", "echo \"T1\"")));
+
+        etsd.execute()?;
+
+        let yaml = serde_yaml::to_string(&etsd.report)?;
+        assert!(yaml.contains("total:\n- name: Test 1\n  energy: 0.0\n"));
+
+        let details: Vec<&TestReport> = etsd.report.details().collect();
+        assert_eq!(2, details.len());
+        let (first, second) = (details[0], details[1]);
+        assert!(first.start.unwrap() <= first.end.unwrap());
+        assert!(first.end.unwrap() <= second.start.unwrap());
+        assert_eq!(
+            first.end.unwrap() - first.start.unwrap(),
+            first.duration.unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn etsdiff_execute_randomized() -> Result<(), Box<dyn Error>> {
+        let mut etsd = ETSdiff::new();
+        etsd.enable_components(&[])?;
+        etsd.set_scheduler(SchedulerType::RandomizedScheduler(Some(7)), 3);
+        etsd.tests
+            .push(Box::new(SystemCallTest::new("Test 1", "echo \"T1\"")));
+        etsd.tests
+            .push(Box::new(SystemCallTest::new("Test 2", "echo \"T2\"")));
+
+        etsd.execute()?;
+
+        let schedule = etsd.report.schedule().unwrap();
+        assert_eq!(Some(7), schedule.seed);
+        let run: Vec<&str> = etsd.report.details().map(|tr| tr.name()).collect();
+        assert_eq!(run, schedule.order);
+
+        let yaml = serde_yaml::to_string(&etsd.report)?;
+        assert!(yaml.starts_with("schedule:\n  seed: 7\n  order:\n  - Test"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn etsdiff_execute_warmup() -> Result<(), Box<dyn Error>> {
+        let mut etsd = ETSdiff::new();
+        etsd.enable_components(&[])?;
+        etsd.set_warmup(1);
+        let mut test = SystemCallTest::new("Test 1", "echo \"T1\"");
+        test.set_warmup(2);
+        etsd.tests.push(Box::new(test));
+        etsd.tests
+            .push(Box::new(SystemCallTest::new("Test 2", "echo \"T2\"")));
+
+        etsd.execute()?;
+
+        let details: Vec<(&str, bool)> = etsd
+            .report
+            .details()
+            .map(|tr| (tr.name(), tr.warmup))
+            .collect();
+        assert_eq!(
+            vec![
+                ("Test 1", true),
+                ("Test 1", true),
+                ("Test 2", true),
+                ("Test 1", false),
+                ("Test 2", false),
+                ("Test 1", false),
+                ("Test 2", false),
+            ],
+            details
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn etsdiff_execute_adaptive() -> Result<(), Box<dyn Error>> {
+        let mut etsd = ETSdiff::new();
+        etsd.enable_components(&[])?;
+        etsd.set_scheduler(
+            SchedulerType::AdaptiveScheduler {
+                min_iteration: 3,
+                target_width: 0.05,
+                time_budget: None,
+            },
+            10,
+        );
+        etsd.tests
+            .push(Box::new(SystemCallTest::new("Test 1", "echo \"T1\"")));
+        etsd.tests
+            .push(Box::new(SystemCallTest::new("Test 2", "echo \"T2\"")));
+
+        etsd.execute()?;
+
+        // without component all values are 0: converged at the minimum
+        assert_eq!(6, etsd.report.details().count());
+        assert_eq!(
+            vec!["Test 1", "Test 2", "Test 1", "Test 2", "Test 1", "Test 2"],
+            etsd.report.schedule().unwrap().order
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn etsdiff_execute_cooldown() -> Result<(), Box<dyn Error>> {
+        let mut etsd = ETSdiff::new();
+        etsd.enable_components(&[])?;
+        let mut cooldown = Cooldown::new();
+        cooldown.set_root("/tmp/etsdiff_execute_cooldown");
+        cooldown.interval = Duration::from_millis(10);
+        etsd.set_cooldown(cooldown);
+        etsd.tests
+            .push(Box::new(SystemCallTest::new("Test 1", "echo \"T1\"")));
+
+        etsd.execute()?;
+
+        for tr in etsd.report.details() {
+            let conditions = tr.pre_test.as_ref().unwrap();
+            assert!(conditions.quiet);
+            assert!(conditions.temperature.is_none());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn etsdiff_stop_started_components_on_error() -> Result<(), Box<dyn Error>> {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let probe = |name: &str, fail_start: bool| -> Box<dyn ETSComponent> {
+            Box::new(Probe {
+                name: name.into(),
+                fail_start,
+                log: log.clone(),
+            })
+        };
+
+        // a component fails to start
+        let mut etsd = ETSdiff::new();
+        etsd.enable_components(&[])?;
+        etsd.set_component(probe("a", false));
+        etsd.set_component(probe("b", true));
+        etsd.set_component(probe("c", false));
+        etsd.tests
+            .push(Box::new(SystemCallTest::new("Test 1", "echo \"T1\"")));
+
+        assert!(etsd.execute().is_err());
+        assert_eq!(vec!["start a", "start b", "stop a"], *log.borrow());
+
+        // the test fails
+        log.borrow_mut().clear();
+        let mut etsd = ETSdiff::new();
+        etsd.enable_components(&[])?;
+        etsd.set_component(probe("a", false));
+        etsd.set_component(probe("b", false));
+        etsd.tests
+            .push(Box::new(SystemCallTest::new("Test 1", "ls /unknowpath")));
+
+        assert!(etsd.execute().is_err());
+        assert_eq!(
+            vec!["start a", "start b", "stop b", "stop a"],
+            *log.borrow()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn etsdiff_execute_abort_on_component_error() {
+        let mut etsd = ETSdiff::new();
+        etsd.set_e_component(EnergySourceType::Replay("/unknowpath".into()));
+        etsd.tests
+            .push(Box::new(SystemCallTest::new("Test 1", "echo \"T1\"")));
+
+        assert!(etsd.execute().is_err());
     }
 
     #[test]
@@ -393,6 +827,14 @@ mod tests {
         etsd.set_scheduler(SchedulerType::StageredScheduler, 7);
         assert!(etsd.scheduler.is_some());
         assert_eq!(7, etsd.scheduler.unwrap().nb_iteration());
+
+        let mut etsd = ETSdiff::new();
+        etsd.set_scheduler(SchedulerType::RandomizedScheduler(Some(42)), 3);
+        assert_eq!(Some(42), etsd.scheduler.as_ref().unwrap().seed());
+
+        // a seed is drawn, to be reported
+        etsd.set_scheduler(SchedulerType::RandomizedScheduler(None), 3);
+        assert!(etsd.scheduler.unwrap().seed().is_some());
     }
 
     #[test]
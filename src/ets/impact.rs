@@ -0,0 +1,101 @@
+// SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use serde::{Deserialize, Serialize};
+
+use super::report::TestReport;
+
+const JOULES_PER_KWH: f64 = 3_600_000.0;
+const OCTETS_PER_GB: f64 = 1_000_000_000.0;
+
+/// Factors turning ETS values into energy (kWh) and carbon (gCO2e).
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ImpactModel {
+    /// Grid carbon intensity, gCO2e per kWh.
+    pub carbon_intensity: f64,
+    /// Network energy, kWh per GB (10^9 octets) transferred.
+    pub network_kwh_per_gb: f64,
+    /// Storage energy, kWh per GB stored for one year.
+    pub storage_kwh_per_gb_year: f64,
+    /// How long the stored data is kept.
+    pub retention_days: f64,
+    /// Primary energy per final (electric) energy.
+    pub primary_energy_factor: f64,
+}
+
+impl ImpactModel {
+    pub fn new(
+        carbon_intensity: f64,
+        network_kwh_per_gb: f64,
+        storage_kwh_per_gb_year: f64,
+    ) -> Self {
+        Self {
+            carbon_intensity,
+            network_kwh_per_gb,
+            storage_kwh_per_gb_year,
+            retention_days: 365.0,
+            primary_energy_factor: 1.0,
+        }
+    }
+
+    pub fn impact(&self, tr: &TestReport) -> ImpactReport {
+        let energy_kwh = tr.energy / JOULES_PER_KWH;
+        let network_kwh = tr.transfer as f64 / OCTETS_PER_GB * self.network_kwh_per_gb;
+        let storage_kwh =
+            tr.storage as f64 / OCTETS_PER_GB * self.storage_kwh_per_gb_year * self.retention_days
+                / 365.0;
+        let total_kwh = energy_kwh + network_kwh + storage_kwh;
+        ImpactReport {
+            name: tr.name().into(),
+            energy_kwh,
+            network_kwh,
+            storage_kwh,
+            total_kwh,
+            primary_kwh: total_kwh * self.primary_energy_factor,
+            carbon: total_kwh * self.carbon_intensity,
+        }
+    }
+}
+
+// ===
+
+/// Footprint of a test: energy of each ETS indicator (kWh), total final and
+/// primary energy (kWh) and carbon (gCO2e).
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ImpactReport {
+    name: String,
+    pub energy_kwh: f64,
+    pub network_kwh: f64,
+    pub storage_kwh: f64,
+    pub total_kwh: f64,
+    pub primary_kwh: f64,
+    pub carbon: f64,
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impact_model() {
+        let mut model = ImpactModel::new(50.0, 0.1, 2.0);
+        model.retention_days = 730.0;
+        model.primary_energy_factor = 2.0;
+
+        let mut tr = TestReport::new("Test 1");
+        tr.energy = 36_000.0;
+        tr.transfer = 1_000_000_000;
+        tr.storage = 500_000_000;
+
+        let impact = model.impact(&tr);
+        assert_eq!("Test 1", impact.name);
+        assert_eq!(0.01, impact.energy_kwh);
+        assert_eq!(0.1, impact.network_kwh);
+        assert_eq!(2.0, impact.storage_kwh);
+        assert_eq!(2.11, impact.total_kwh);
+        assert_eq!(4.22, impact.primary_kwh);
+        assert_eq!(105.5, impact.carbon);
+    }
+}
@@ -1,8 +1,26 @@
 // SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::report::TestReport;
+
 pub enum SchedulerType {
     StageredScheduler,
+    BlockedScheduler,
+    LatinSquareScheduler,
+    /// Seed of the shuffles, drawn at random when None.
+    RandomizedScheduler(Option<u64>),
+    /// Iterates until convergence, `nb_iteration` being the maximum.
+    AdaptiveScheduler {
+        min_iteration: u32,
+        target_width: f64,
+        time_budget: Option<Duration>,
+    },
 }
 
 // ===
@@ -10,6 +28,16 @@ pub enum SchedulerType {
 pub trait IterationScheduler {
     fn nb_iteration(&self) -> u32;
     fn get_ordered_list(&self, nb_tests: u32) -> Vec<u32>;
+    /// Next test to run, None when the campaign is over. `done` holds the
+    /// reports of the iterations run so far (not the warm-up runs), `elapsed`
+    /// the time since the campaign start.
+    fn next_test(&self, nb_tests: u32, done: &[&TestReport], _elapsed: Duration) -> Option<u32> {
+        self.get_ordered_list(nb_tests).get(done.len()).copied()
+    }
+    /// Seed giving the same order again, for schedulers drawing it at random.
+    fn seed(&self) -> Option<u64> {
+        None
+    }
 }
 
 // ===
@@ -39,6 +67,233 @@ impl IterationScheduler for StageredScheduler {
     }
 }
 
+// ===
+
+/// All the iterations of the first test, then all the iterations of the
+/// second one...
+pub struct BlockedScheduler {
+    nb_iteration: u32,
+}
+
+impl BlockedScheduler {
+    pub fn new(nb_iteration: u32) -> Self {
+        Self { nb_iteration }
+    }
+}
+
+impl IterationScheduler for BlockedScheduler {
+    fn nb_iteration(&self) -> u32 {
+        self.nb_iteration
+    }
+    fn get_ordered_list(&self, nb_tests: u32) -> Vec<u32> {
+        let mut vec = Vec::new();
+        for t in 0..nb_tests {
+            for _i in 0..self.nb_iteration {
+                vec.push(t);
+            }
+        }
+        vec
+    }
+}
+
+// ===
+
+/// Each iteration runs all the tests once, in the order of a row of a Williams
+/// Latin square: over a full square (n rows for n tests, 2n when n is odd),
+/// each test is run once at each position and right after each other test
+/// once, so order and carry-over effects are balanced.
+pub struct LatinSquareScheduler {
+    nb_iteration: u32,
+}
+
+impl LatinSquareScheduler {
+    pub fn new(nb_iteration: u32) -> Self {
+        Self { nb_iteration }
+    }
+
+    /// Rows of the Williams square of `n` tests.
+    pub fn square(n: u32) -> Vec<Vec<u32>> {
+        // 0, 1, n-1, 2, n-2...
+        let first: Vec<u32> = (0..n)
+            .map(|j| {
+                if j % 2 == 1 {
+                    j.div_ceil(2)
+                } else {
+                    (n - j / 2) % n
+                }
+            })
+            .collect();
+        let mut rows: Vec<Vec<u32>> = (0..n)
+            .map(|i| first.iter().map(|t| (t + i) % n).collect())
+            .collect();
+        if n % 2 == 1 {
+            let reversed: Vec<Vec<u32>> = rows
+                .iter()
+                .map(|row| row.iter().rev().copied().collect())
+                .collect();
+            rows.extend(reversed);
+        }
+        rows
+    }
+}
+
+impl IterationScheduler for LatinSquareScheduler {
+    fn nb_iteration(&self) -> u32 {
+        self.nb_iteration
+    }
+    fn get_ordered_list(&self, nb_tests: u32) -> Vec<u32> {
+        let square = Self::square(nb_tests);
+        let mut vec = Vec::new();
+        for i in 0..self.nb_iteration as usize {
+            if let Some(row) = square.get(i % square.len().max(1)) {
+                vec.extend(row);
+            }
+        }
+        vec
+    }
+}
+
+// ===
+
+/// Each iteration runs all the tests once, in an order shuffled with a seeded
+/// generator: drifts (heat, caches...) don't always favor the same test, and
+/// the same seed gives the same order.
+pub struct RandomizedScheduler {
+    nb_iteration: u32,
+    seed: u64,
+}
+
+impl RandomizedScheduler {
+    pub fn new(nb_iteration: u32, seed: u64) -> Self {
+        Self { nb_iteration, seed }
+    }
+}
+
+impl IterationScheduler for RandomizedScheduler {
+    fn nb_iteration(&self) -> u32 {
+        self.nb_iteration
+    }
+    fn get_ordered_list(&self, nb_tests: u32) -> Vec<u32> {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let mut vec = Vec::new();
+        for _i in 0..self.nb_iteration {
+            let mut round: Vec<u32> = (0..nb_tests).collect();
+            round.shuffle(&mut rng);
+            vec.append(&mut round);
+        }
+        vec
+    }
+    fn seed(&self) -> Option<u64> {
+        Some(self.seed)
+    }
+}
+
+// ===
+
+/// Runs rounds of all the tests (in the configuration order) until the 95%
+/// confidence interval of every value (energy, transfer, storage, metrics) of
+/// every test is narrower than `target_width` times its mean. It also stops
+/// after `max_iteration` rounds or, once a round is done, when the time budget
+/// is spent.
+pub struct AdaptiveScheduler {
+    min_iteration: u32,
+    max_iteration: u32,
+    target_width: f64,
+    time_budget: Option<Duration>,
+}
+
+impl AdaptiveScheduler {
+    /// At least 2 iterations are run to get an interval.
+    pub fn new(min_iteration: u32, max_iteration: u32, target_width: f64) -> Self {
+        Self {
+            min_iteration: min_iteration.max(2),
+            max_iteration,
+            target_width,
+            time_budget: None,
+        }
+    }
+    pub fn set_time_budget(&mut self, time_budget: Duration) {
+        self.time_budget = Some(time_budget);
+    }
+
+    fn converged(&self, done: &[&TestReport]) -> bool {
+        let mut values = BTreeMap::<(&str, &str), Vec<f64>>::new();
+        for tr in done {
+            let mut push =
+                |metric, value| values.entry((tr.name(), metric)).or_default().push(value);
+            push("energy", tr.energy);
+            push("transfer", tr.transfer as f64);
+            push("storage", tr.storage as f64);
+            for (name, value) in &tr.metrics {
+                push(name, *value);
+            }
+        }
+        values
+            .values()
+            .all(|v| relative_width(v) <= self.target_width)
+    }
+}
+
+impl IterationScheduler for AdaptiveScheduler {
+    /// The maximum number of iterations.
+    fn nb_iteration(&self) -> u32 {
+        self.max_iteration
+    }
+    /// The order with the maximum number of iterations.
+    fn get_ordered_list(&self, nb_tests: u32) -> Vec<u32> {
+        StageredScheduler::new(self.max_iteration).get_ordered_list(nb_tests)
+    }
+    fn next_test(&self, nb_tests: u32, done: &[&TestReport], elapsed: Duration) -> Option<u32> {
+        if nb_tests == 0 {
+            return None;
+        }
+        // rounds are always completed
+        let position = done.len() as u32 % nb_tests;
+        if position != 0 {
+            return Some(position);
+        }
+        let rounds = done.len() as u32 / nb_tests;
+        if rounds >= self.max_iteration {
+            println!("  Maximum number of iterations reached: {rounds}");
+            return None;
+        }
+        if rounds > 0 && self.time_budget.is_some_and(|budget| elapsed >= budget) {
+            println!("  Time budget spent after {rounds} iterations");
+            return None;
+        }
+        if rounds >= self.min_iteration && self.converged(done) {
+            println!("  Converged after {rounds} iterations");
+            return None;
+        }
+        Some(0)
+    }
+}
+
+// Student t quantiles (97.5%) for 1 to 30 degrees of freedom.
+const STUDENT_T_975: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// Width of the 95% confidence interval of the mean, relative to the mean.
+/// Infinite with less than 2 values.
+pub fn relative_width(values: &[f64]) -> f64 {
+    let n = values.len();
+    if n < 2 {
+        return f64::INFINITY;
+    }
+    let mean = values.iter().sum::<f64>() / n as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+    let t = STUDENT_T_975.get(n - 2).copied().unwrap_or(1.96);
+    let width = 2.0 * t * (variance / n as f64).sqrt();
+    if width == 0.0 {
+        0.0
+    } else {
+        width / mean.abs()
+    }
+}
+
 // -----------------------------------------------------------------------------
 
 #[cfg(test)]
@@ -65,4 +320,127 @@ mod tests {
         assert_eq!(1, list[4]);
         assert_eq!(2, list[5]);
     }
+
+    #[test]
+    fn test_randomized_list() {
+        let scheduler = RandomizedScheduler::new(4, 42);
+        assert_eq!(4, scheduler.nb_iteration());
+        assert_eq!(Some(42), scheduler.seed());
+
+        let list = scheduler.get_ordered_list(3);
+        assert_eq!(12, list.len());
+        // each iteration runs every test once
+        for round in list.chunks(3) {
+            let mut round = round.to_vec();
+            round.sort();
+            assert_eq!(vec![0, 1, 2], round);
+        }
+
+        // same seed, same order
+        assert_eq!(list, RandomizedScheduler::new(4, 42).get_ordered_list(3));
+        assert_ne!(list, RandomizedScheduler::new(4, 43).get_ordered_list(3));
+        assert_ne!(list, StageredScheduler::new(4).get_ordered_list(3));
+    }
+
+    #[test]
+    fn test_relative_width() {
+        assert_eq!(f64::INFINITY, relative_width(&[]));
+        assert_eq!(f64::INFINITY, relative_width(&[1.0]));
+        assert_eq!(0.0, relative_width(&[0.0, 0.0]));
+        assert_eq!(0.0, relative_width(&[5.0, 5.0, 5.0]));
+        assert_eq!(f64::INFINITY, relative_width(&[-1.0, 1.0]));
+        // mean 10, standard deviation 1, t(2) = 4.303
+        let width = relative_width(&[9.0, 10.0, 11.0]);
+        assert!((width - 2.0 * 4.303 / 3f64.sqrt() / 10.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_adaptive_next_test() {
+        let run = |scheduler: &AdaptiveScheduler, energies: &[f64], elapsed: u64| {
+            let reports: Vec<TestReport> = energies
+                .iter()
+                .enumerate()
+                .map(|(i, energy)| {
+                    let mut tr = TestReport::new(&format!("Test {}", i % 2));
+                    tr.energy = *energy;
+                    tr
+                })
+                .collect();
+            let done: Vec<&TestReport> = reports.iter().collect();
+            scheduler.next_test(2, &done, Duration::from_secs(elapsed))
+        };
+
+        let mut scheduler = AdaptiveScheduler::new(1, 4, 0.05);
+        assert_eq!(4, scheduler.nb_iteration());
+        assert_eq!(8, scheduler.get_ordered_list(2).len());
+
+        assert_eq!(Some(0), run(&scheduler, &[], 0));
+        // the round is completed
+        assert_eq!(Some(1), run(&scheduler, &[10.0], 0));
+        // at least 2 iterations
+        assert_eq!(Some(0), run(&scheduler, &[10.0, 20.0], 0));
+        assert_eq!(None, run(&scheduler, &[10.0, 20.0, 10.0, 20.0], 0));
+        // Test 1 hasn't converged
+        let spread = [10.0, 20.0, 10.0, 30.0, 10.0, 20.0];
+        assert_eq!(Some(0), run(&scheduler, &spread, 0));
+        assert_eq!(
+            None,
+            run(&scheduler, &[spread.as_slice(), &[10.0, 30.0]].concat(), 0)
+        );
+
+        scheduler.set_time_budget(Duration::from_secs(60));
+        assert_eq!(Some(0), run(&scheduler, &[], 100));
+        assert_eq!(Some(1), run(&scheduler, &[10.0], 100));
+        assert_eq!(None, run(&scheduler, &[10.0, 20.0], 100));
+        assert_eq!(Some(0), run(&scheduler, &spread, 59));
+    }
+
+    #[test]
+    fn test_blocked_list() {
+        let scheduler = BlockedScheduler::new(2);
+        assert_eq!(2, scheduler.nb_iteration());
+        assert_eq!(vec![0, 0, 1, 1, 2, 2], scheduler.get_ordered_list(3));
+    }
+
+    #[test]
+    fn test_latin_square() {
+        assert_eq!(
+            vec![
+                vec![0, 1, 3, 2],
+                vec![1, 2, 0, 3],
+                vec![2, 3, 1, 0],
+                vec![3, 0, 2, 1]
+            ],
+            LatinSquareScheduler::square(4)
+        );
+
+        for n in 1..8 {
+            let square = LatinSquareScheduler::square(n);
+            let nb_rows = if n % 2 == 0 { n } else { 2 * n };
+            assert_eq!(nb_rows as usize, square.len());
+            // each test at each position, each pair of successive tests once
+            let mut positions = BTreeMap::new();
+            let mut pairs = BTreeMap::new();
+            for row in &square {
+                for (position, t) in row.iter().enumerate() {
+                    *positions.entry((position, *t)).or_insert(0) += 1;
+                }
+                for pair in row.windows(2) {
+                    *pairs.entry((pair[0], pair[1])).or_insert(0) += 1;
+                }
+            }
+            assert_eq!((n * n) as usize, positions.len());
+            assert!(positions.values().all(|c| *c == nb_rows / n));
+            assert_eq!((n * (n - 1)) as usize, pairs.len());
+            assert!(pairs.values().all(|c| *c == nb_rows / n));
+        }
+
+        let scheduler = LatinSquareScheduler::new(5);
+        assert_eq!(5, scheduler.nb_iteration());
+        assert_eq!(
+            vec![0, 1, 2, 1, 2, 0, 2, 0, 1, 2, 1, 0, 0, 2, 1],
+            scheduler.get_ordered_list(3)
+        );
+        assert!(scheduler.get_ordered_list(0).is_empty());
+    }
 }
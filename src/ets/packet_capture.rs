@@ -0,0 +1,861 @@
+// SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use ipnet::IpNet;
+use rtshark::{RTShark, RTSharkBuilder};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use super::bpf::compile_filter;
+use super::pcap::read_frames;
+use super::protocol::ProtocolAnalyzer;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CaptureType {
+    AfPacket,
+    TShark,
+    Pcap(String),
+}
+
+// ===
+
+/// Capture settings of the campaign or of a service (unset values of a
+/// service fall back to the campaign ones).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CaptureSettings {
+    /// Network interface, every interface when unset or "any".
+    pub interface: Option<String>,
+    /// Hosts or networks the packets come from or go to.
+    pub hosts: Vec<IpNet>,
+    /// Extra pcap filter expression (TShark backend only).
+    pub filter: Option<String>,
+}
+
+impl CaptureSettings {
+    /// Capture on every interface from or to localhost.
+    pub fn localhost() -> Self {
+        Self {
+            interface: None,
+            hosts: vec![IpNet::from(IpAddr::V4(Ipv4Addr::LOCALHOST))],
+            filter: None,
+        }
+    }
+}
+
+/// Packets from or to one of `ports` of `hosts` (any host when empty).
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureRule {
+    pub interface: Option<String>,
+    pub hosts: Vec<IpNet>,
+    pub ports: Vec<u32>,
+    pub filter: Option<String>,
+}
+
+impl CaptureRule {
+    pub fn new(ports: &[u32], settings: &CaptureSettings, campaign: &CaptureSettings) -> Self {
+        let hosts = if settings.hosts.is_empty() {
+            &campaign.hosts
+        } else {
+            &settings.hosts
+        };
+        let filter = match (&campaign.filter, &settings.filter) {
+            (Some(c), Some(s)) => Some(format!("({c}) and ({s})")),
+            (c, s) => s.clone().or(c.clone()),
+        };
+        Self {
+            interface: settings.interface.clone().or(campaign.interface.clone()),
+            hosts: hosts.clone(),
+            ports: ports.to_vec(),
+            filter,
+        }
+    }
+}
+
+/// Parse an IP address or a network in CIDR notation.
+pub fn parse_host(host: &str) -> Result<IpNet, Box<dyn Error>> {
+    if let Ok(net) = IpNet::from_str(host) {
+        return Ok(net.trunc());
+    }
+    match IpAddr::from_str(host) {
+        Ok(addr) => Ok(IpNet::from(addr)),
+        Err(_) => bail!("Invalid capture host {}", host),
+    }
+}
+
+// ===
+
+/// Captured octets, also split by (source port, destination port) and by
+/// protocol.
+#[derive(Default, Debug, PartialEq)]
+pub struct TransferCounter {
+    pub total: u64,
+    by_ports: HashMap<(u16, u16), u64>,
+    pub protocols: ProtocolAnalyzer,
+}
+
+impl TransferCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Account a captured `packet` (starting at the IP header) of `octets`.
+    pub fn add_packet(&mut self, octets: u64, packet: &[u8]) {
+        self.add(octets, transport_ports(packet));
+        self.protocols.add(octets, packet);
+    }
+    pub fn add(&mut self, octets: u64, ports: Option<(u16, u16)>) {
+        self.total += octets;
+        if let Some(ports) = ports {
+            *self.by_ports.entry(ports).or_default() += octets;
+        }
+    }
+    /// Octets sent to one of `ports`.
+    pub fn ingress(&self, ports: &[u32]) -> u64 {
+        self.by_ports
+            .iter()
+            .filter(|((_, dst), _)| ports.contains(&u32::from(*dst)))
+            .map(|(_, octets)| octets)
+            .sum()
+    }
+    /// Octets sent from one of `ports`.
+    pub fn egress(&self, ports: &[u32]) -> u64 {
+        self.by_ports
+            .iter()
+            .filter(|((src, _), _)| ports.contains(&u32::from(*src)))
+            .map(|(_, octets)| octets)
+            .sum()
+    }
+}
+
+// ===
+
+pub trait PacketCapture {
+    fn capture_type(&self) -> CaptureType;
+    /// Check a capture of the packets matching `rules` can be started.
+    fn validate(&self, rules: &[CaptureRule]) -> Result<(), Box<dyn Error>>;
+    /// Start capturing the packets matching one of `rules`.
+    fn start(&mut self, rules: &[CaptureRule]) -> Result<(), Box<dyn Error>>;
+    /// Stop the capture and return the captured octets.
+    fn stop(&mut self) -> Result<TransferCounter, Box<dyn Error>>;
+}
+
+// ===
+
+const CAPTURE_SNAPLEN: usize = 65_536;
+const CAPTURE_READ_TIMEOUT_US: i64 = 100_000;
+
+/// Built-in capture using an AF_PACKET socket: the kernel only delivers the
+/// packets matching a BPF filter, their length is summed as they arrive.
+/// Octets are counted from the network layer (link layer headers excluded).
+pub struct AfPacketCapture {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<io::Result<TransferCounter>>>,
+}
+
+impl PacketCapture for AfPacketCapture {
+    fn capture_type(&self) -> CaptureType {
+        CaptureType::AfPacket
+    }
+    fn validate(&self, rules: &[CaptureRule]) -> Result<(), Box<dyn Error>> {
+        compile_filter(rules)?;
+        Ok(())
+    }
+    fn start(&mut self, rules: &[CaptureRule]) -> Result<(), Box<dyn Error>> {
+        if self.handle.is_some() {
+            self.stop()?;
+        }
+
+        let mut filter = compile_filter(rules)?;
+        let fd = open_packet_socket(&mut filter)?;
+        let loopback = loopback_index();
+
+        self.stop = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&self.stop);
+        self.handle = Some(std::thread::spawn(move || {
+            read_packets(&fd, loopback, &stop)
+        }));
+        Ok(())
+    }
+    fn stop(&mut self) -> Result<TransferCounter, Box<dyn Error>> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.handle.take() {
+            None => bail!("AF_PACKET capture not started"),
+            Some(handle) => match handle.join() {
+                Ok(counter) => Ok(counter?),
+                Err(_) => bail!("AF_PACKET capture thread panicked"),
+            },
+        }
+    }
+}
+
+impl AfPacketCapture {
+    pub fn new() -> Self {
+        Self {
+            stop: Arc::new(AtomicBool::new(false)),
+            handle: None,
+        }
+    }
+}
+
+impl Default for AfPacketCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for AfPacketCapture {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            let _ = self.stop();
+        }
+    }
+}
+
+// The socket doesn't listen any protocol until bind(): the filter is attached
+// before, so no unfiltered packet can be queued.
+fn open_packet_socket(filter: &mut [libc::sock_filter]) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let prog = libc::sock_fprog {
+        len: filter.len() as libc::c_ushort,
+        filter: filter.as_mut_ptr(),
+    };
+    setsockopt(&fd, libc::SO_ATTACH_FILTER, &prog)?;
+
+    let timeout = libc::timeval {
+        tv_sec: 0,
+        tv_usec: CAPTURE_READ_TIMEOUT_US,
+    };
+    setsockopt(&fd, libc::SO_RCVTIMEO, &timeout)?;
+
+    let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as libc::c_ushort;
+    addr.sll_protocol = (libc::ETH_P_ALL as u16).to_be();
+    let ret = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(fd)
+}
+
+fn setsockopt<T>(fd: &OwnedFd, option: libc::c_int, value: &T) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            value as *const T as *const libc::c_void,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn loopback_index() -> i32 {
+    unsafe { libc::if_nametoindex(c"lo".as_ptr()) as i32 }
+}
+
+// Once stopped, packets already queued are still read before returning.
+fn read_packets(fd: &OwnedFd, loopback: i32, stop: &AtomicBool) -> io::Result<TransferCounter> {
+    let mut counter = TransferCounter::new();
+    let mut buffer = vec![0u8; CAPTURE_SNAPLEN];
+    loop {
+        let stopping = stop.load(Ordering::Relaxed);
+        let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        let mut addr_len = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+        let flags = if stopping {
+            libc::MSG_TRUNC | libc::MSG_DONTWAIT
+        } else {
+            libc::MSG_TRUNC
+        };
+        let len = unsafe {
+            libc::recvfrom(
+                fd.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                flags,
+                &mut addr as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                &mut addr_len,
+            )
+        };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            match err.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut if stopping => break,
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => continue,
+                io::ErrorKind::Interrupted => continue,
+                _ => return Err(err),
+            }
+        }
+        // loopback packets are seen twice: when sent and when received
+        if addr.sll_ifindex == loopback && addr.sll_pkttype == PACKET_OUTGOING {
+            continue;
+        }
+        let captured = (len as usize).min(buffer.len());
+        counter.add_packet(len as u64, &buffer[..captured]);
+    }
+    Ok(counter)
+}
+
+pub const IPPROTO_TCP: u32 = 6;
+pub const IPPROTO_UDP: u32 = 17;
+
+/// Source and destination ports of an IPv4 or IPv6 TCP or UDP packet.
+pub fn transport_ports(packet: &[u8]) -> Option<(u16, u16)> {
+    let (protocol, header_len) = match packet.first()? >> 4 {
+        4 if packet.len() >= 20 => (packet[9], usize::from(packet[0] & 0x0f) * 4),
+        6 if packet.len() >= 40 => (packet[6], 40),
+        _ => return None,
+    };
+    if ![IPPROTO_TCP, IPPROTO_UDP].contains(&u32::from(protocol)) {
+        return None;
+    }
+    let ports = packet.get(header_len..header_len + 4)?;
+    Some((
+        u16::from_be_bytes([ports[0], ports[1]]),
+        u16::from_be_bytes([ports[2], ports[3]]),
+    ))
+}
+
+const PACKET_OUTGOING: u8 = 4;
+
+// ===
+
+/// Software equivalent of `compile_filter` (interfaces are not checked):
+/// ports of an IP `packet` matching one of `rules`.
+pub fn match_packet(packet: &[u8], rules: &[CaptureRule]) -> Option<(u16, u16)> {
+    let (src, dst) = match packet.first()? >> 4 {
+        4 => {
+            // first fragment only
+            if u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]) & 0x1fff != 0 {
+                return None;
+            }
+            let addr = |offset: usize| -> Option<IpAddr> {
+                let b: [u8; 4] = packet.get(offset..offset + 4)?.try_into().ok()?;
+                Some(IpAddr::V4(Ipv4Addr::from(b)))
+            };
+            (addr(12)?, addr(16)?)
+        }
+        6 => {
+            let addr = |offset: usize| -> Option<IpAddr> {
+                let b: [u8; 16] = packet.get(offset..offset + 16)?.try_into().ok()?;
+                Some(IpAddr::V6(Ipv6Addr::from(b)))
+            };
+            (addr(8)?, addr(24)?)
+        }
+        _ => return None,
+    };
+    let (sport, dport) = transport_ports(packet)?;
+
+    rules
+        .iter()
+        .any(|rule| {
+            (rule.hosts.is_empty()
+                || rule
+                    .hosts
+                    .iter()
+                    .any(|h| h.contains(&src) || h.contains(&dst)))
+                && (rule.ports.contains(&u32::from(sport))
+                    || rule.ports.contains(&u32::from(dport)))
+        })
+        .then_some((sport, dport))
+}
+
+/// Octets of the frames of a pcap(ng) file matching one of `rules`.
+/// Like with the AF_PACKET socket, octets are counted from the IP header.
+pub fn pcap_transfer(path: &str, rules: &[CaptureRule]) -> Result<TransferCounter, Box<dyn Error>> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) => bail!("Can't read capture file {}: {}", path, err),
+    };
+    let mut counter = TransferCounter::new();
+    read_frames(&data, |frame| {
+        if let Some((octets, packet)) = frame.ip_packet() {
+            if match_packet(packet, rules).is_some() {
+                counter.add_packet(octets, packet);
+            }
+        }
+    })?;
+    Ok(counter)
+}
+
+// ===
+
+/// Offline capture: packets are read from an existing pcap(ng) file when the
+/// test ends, with the same filter as a live capture.
+pub struct PcapFileCapture {
+    path: String,
+    rules: Vec<CaptureRule>,
+}
+
+impl PacketCapture for PcapFileCapture {
+    fn capture_type(&self) -> CaptureType {
+        CaptureType::Pcap(self.path.clone())
+    }
+    fn validate(&self, rules: &[CaptureRule]) -> Result<(), Box<dyn Error>> {
+        if rules.iter().all(|r| r.ports.is_empty()) {
+            bail!("No port to capture");
+        }
+        if let Some(filter) = rules.iter().find_map(|r| r.filter.as_ref()) {
+            bail!(
+                "Capture filter \"{}\" needs the TShark transfer backend",
+                filter
+            );
+        }
+        Ok(())
+    }
+    fn start(&mut self, rules: &[CaptureRule]) -> Result<(), Box<dyn Error>> {
+        self.validate(rules)?;
+        if !std::path::Path::new(&self.path).is_file() {
+            bail!("Capture file {} not found", self.path);
+        }
+        self.rules = rules.to_vec();
+        Ok(())
+    }
+    fn stop(&mut self) -> Result<TransferCounter, Box<dyn Error>> {
+        pcap_transfer(&self.path, &self.rules)
+    }
+}
+
+impl PcapFileCapture {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.into(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+// ===
+
+const TSHARK_PCAP_PATH: &str = "/tmp/etsdiff.pcap";
+
+/// Capture delegated to tshark, written to a pcapng file and read back to sum
+/// the length of the IP packets of the frames.
+pub struct TSharkCapture {
+    rtshark: Option<RTShark>,
+}
+
+impl PacketCapture for TSharkCapture {
+    fn capture_type(&self) -> CaptureType {
+        CaptureType::TShark
+    }
+    // The filter is compiled by dumpcap (shipped with tshark) on each interface.
+    fn validate(&self, rules: &[CaptureRule]) -> Result<(), Box<dyn Error>> {
+        let filter = tshark_filter(rules)?;
+        for interface in tshark_interfaces(rules) {
+            let output = match Command::new("dumpcap")
+                .args(["-i", &interface, "-f", &filter, "-d"])
+                .output()
+            {
+                Ok(output) => output,
+                Err(err) => bail!("Can't run dumpcap to check capture filter: {}", err),
+            };
+            if !output.status.success() {
+                bail!(
+                    "Invalid capture filter \"{}\" on {}: {}",
+                    filter,
+                    interface,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+        }
+        Ok(())
+    }
+    fn start(&mut self, rules: &[CaptureRule]) -> Result<(), Box<dyn Error>> {
+        self.validate(rules)?;
+        let filter = tshark_filter(rules)?;
+        let interfaces = tshark_interfaces(rules);
+
+        let mut builder = RTSharkBuilder::builder().input_path(&interfaces[0]);
+        for interface in &interfaces[1..] {
+            builder = builder.input_path(interface);
+        }
+        let builder = builder
+            .output_path(TSHARK_PCAP_PATH)
+            .live_capture()
+            .capture_filter(&filter);
+
+        match builder.spawn() {
+            Err(err) => {
+                bail!("Error running tshark writter: {}", err);
+            }
+            Ok(rtshark) => {
+                self.rtshark = Some(rtshark);
+                std::thread::sleep(std::time::Duration::from_millis(1000)); // TODO better implementation
+            }
+        };
+        Ok(())
+    }
+    fn stop(&mut self) -> Result<TransferCounter, Box<dyn Error>> {
+        if let Some(s) = &mut self.rtshark {
+            s.kill();
+            self.rtshark = None;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1000)); // TODO better implementation
+
+        let data = std::fs::read(TSHARK_PCAP_PATH)?;
+        let counter = tshark_transfer(&data)?;
+
+        std::fs::remove_file(TSHARK_PCAP_PATH)?;
+        Ok(counter)
+    }
+}
+
+// Every frame was already filtered by tshark, link layer headers are not
+// counted (same octets as the other backends).
+fn tshark_transfer(data: &[u8]) -> Result<TransferCounter, Box<dyn Error>> {
+    let mut counter = TransferCounter::new();
+    read_frames(data, |frame| {
+        if let Some((octets, packet)) = frame.ip_packet() {
+            counter.add_packet(octets, packet)
+        }
+    })?;
+    Ok(counter)
+}
+
+impl TSharkCapture {
+    pub fn new() -> Self {
+        Self { rtshark: None }
+    }
+}
+
+/// pcap filter expression of `rules`.
+pub fn tshark_filter(rules: &[CaptureRule]) -> Result<String, Box<dyn Error>> {
+    let mut filters = Vec::new();
+    for rule in rules.iter().filter(|r| !r.ports.is_empty()) {
+        let mut parts = Vec::new();
+        if !rule.hosts.is_empty() {
+            let hosts: Vec<String> = rule
+                .hosts
+                .iter()
+                .map(|h| {
+                    if h.prefix_len() == h.max_prefix_len() {
+                        format!("host {}", h.addr())
+                    } else {
+                        format!("net {h}")
+                    }
+                })
+                .collect();
+            parts.push(format!("({})", hosts.join(" or ")));
+        }
+        let ports: Vec<String> = rule.ports.iter().map(|p| format!("port {p}")).collect();
+        parts.push(format!("({})", ports.join(" or ")));
+        if let Some(filter) = &rule.filter {
+            parts.push(format!("({filter})"));
+        }
+        filters.push(format!("({})", parts.join(" and ")));
+    }
+    if filters.is_empty() {
+        bail!("No port to capture");
+    }
+    Ok(filters.join(" or "))
+}
+
+// tshark applies the same filter on every interface: with "any" in the list
+// only "any" is captured (packets would be seen twice otherwise).
+fn tshark_interfaces(rules: &[CaptureRule]) -> Vec<String> {
+    let mut interfaces = Vec::new();
+    for rule in rules.iter().filter(|r| !r.ports.is_empty()) {
+        match rule.interface.as_deref() {
+            None | Some("any") => return vec!["any".into()],
+            Some(interface) => {
+                if !interfaces.iter().any(|i| i == interface) {
+                    interfaces.push(interface.into());
+                }
+            }
+        }
+    }
+    if interfaces.is_empty() {
+        interfaces.push("any".into());
+    }
+    interfaces
+}
+
+impl Default for TSharkCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ets::bpf::{self, compile_filter};
+    use crate::ets::pcap::tests::{pcap_file, tcp_packet};
+    use crate::ets::pcap::{
+        LINKTYPE_ETHERNET, LINKTYPE_LINUX_SLL, LINKTYPE_LINUX_SLL2, LINKTYPE_RAW,
+    };
+
+    fn localhost_rule(ports: &[u32]) -> CaptureRule {
+        CaptureRule::new(
+            ports,
+            &CaptureSettings::default(),
+            &CaptureSettings::localhost(),
+        )
+    }
+
+    #[test]
+    fn capture_rule_settings() -> Result<(), Box<dyn Error>> {
+        let campaign = CaptureSettings {
+            interface: Some("docker0".into()),
+            hosts: vec![parse_host("172.17.0.0/16")?],
+            filter: Some("tcp".into()),
+        };
+
+        let rule = CaptureRule::new(&[80], &CaptureSettings::default(), &campaign);
+        assert_eq!(Some("docker0".into()), rule.interface);
+        assert_eq!(campaign.hosts, rule.hosts);
+        assert_eq!(Some("tcp".into()), rule.filter);
+
+        let service = CaptureSettings {
+            interface: Some("veth1".into()),
+            hosts: vec![parse_host("10.0.0.2")?, parse_host("fd00::/8")?],
+            filter: Some("not port 22".into()),
+        };
+        let rule = CaptureRule::new(&[80], &service, &campaign);
+        assert_eq!(Some("veth1".into()), rule.interface);
+        assert_eq!(service.hosts, rule.hosts);
+        assert_eq!(Some("(tcp) and (not port 22)".into()), rule.filter);
+        assert_eq!(vec![80], rule.ports);
+
+        Ok(())
+    }
+
+    #[test]
+    fn capture_parse_host() {
+        assert_eq!("127.0.0.1/32", parse_host("127.0.0.1").unwrap().to_string());
+        assert_eq!("10.0.0.0/8", parse_host("10.1.2.3/8").unwrap().to_string());
+        assert_eq!("::1/128", parse_host("::1").unwrap().to_string());
+        assert!(parse_host("localhost").is_err());
+    }
+
+    #[test]
+    fn transfer_counter_directions() {
+        let mut counter = TransferCounter::new();
+        // client -> web, web -> db, db -> web, web -> client
+        counter.add(100, Some((50000, 8080)));
+        counter.add(40, Some((50001, 5432)));
+        counter.add(400, Some((5432, 50001)));
+        counter.add(1000, Some((8080, 50000)));
+        counter.add(7, None);
+
+        assert_eq!(1547, counter.total);
+        assert_eq!(100, counter.ingress(&[8080]));
+        assert_eq!(1000, counter.egress(&[8080]));
+        assert_eq!(40, counter.ingress(&[5432]));
+        assert_eq!(400, counter.egress(&[5432]));
+        assert_eq!(140, counter.ingress(&[8080, 5432]));
+        assert_eq!(0, counter.ingress(&[]));
+    }
+
+    #[test]
+    fn transport_ports_ipv4() {
+        let mut packet = vec![0u8; 28];
+        packet[0] = 0x45;
+        packet[9] = 17;
+        packet[20..24].copy_from_slice(&[0x1f, 0x90, 0x10, 0xe6]);
+        assert_eq!(Some((8080, 4326)), transport_ports(&packet));
+
+        // IP options
+        packet[0] = 0x46;
+        assert_eq!(None, transport_ports(&packet[..24]));
+        packet.splice(20..20, [0u8; 4]);
+        assert_eq!(Some((8080, 4326)), transport_ports(&packet));
+
+        // ICMP
+        packet[9] = 1;
+        assert_eq!(None, transport_ports(&packet));
+    }
+
+    #[test]
+    fn transport_ports_ipv6() {
+        let mut packet = vec![0u8; 48];
+        packet[0] = 0x60;
+        packet[6] = 6;
+        packet[40..44].copy_from_slice(&[0x1f, 0x90, 0x10, 0xe6]);
+        assert_eq!(Some((8080, 4326)), transport_ports(&packet));
+        assert_eq!(None, transport_ports(&packet[..42]));
+    }
+
+    #[test]
+    fn match_packet_filter() -> Result<(), Box<dyn Error>> {
+        let packet = tcp_packet(50000, 8080);
+        let rules = [localhost_rule(&[8080])];
+        assert_eq!(Some((50000, 8080)), match_packet(&packet, &rules));
+        assert_eq!(None, match_packet(&packet, &[localhost_rule(&[4326])]));
+
+        let mut rule = localhost_rule(&[8080]);
+        rule.hosts = vec![parse_host("10.0.0.0/8")?];
+        assert_eq!(None, match_packet(&packet, &[rule.clone()]));
+        rule.hosts = vec![parse_host("127.0.0.0/8")?];
+        assert_eq!(Some((50000, 8080)), match_packet(&packet, &[rule.clone()]));
+        rule.hosts = Vec::new();
+        assert_eq!(Some((50000, 8080)), match_packet(&packet, &[rule]));
+
+        let mut fragment = packet.clone();
+        fragment[7] = 1;
+        assert_eq!(None, match_packet(&fragment, &rules));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pcap_file_capture() -> Result<(), Box<dyn Error>> {
+        let path = "/tmp/etsdiff_pcap_file_capture.pcap";
+        let file = pcap_file(
+            LINKTYPE_RAW,
+            &[
+                (100, tcp_packet(50000, 8080)),
+                (1000, tcp_packet(8080, 50000)),
+                (500, tcp_packet(50001, 22)),
+            ],
+        );
+        std::fs::write(path, file)?;
+
+        let rules = [localhost_rule(&[8080])];
+        let mut capture = PcapFileCapture::new(path);
+        capture.start(&rules)?;
+        let counter = capture.stop()?;
+        assert_eq!(1100, counter.total);
+        assert_eq!(100, counter.ingress(&[8080]));
+        assert_eq!(1000, counter.egress(&[8080]));
+
+        let mut rule = localhost_rule(&[8080]);
+        rule.filter = Some("tcp".into());
+        assert!(capture.start(&[rule]).is_err());
+
+        std::fs::remove_file(path)?;
+        assert!(capture.start(&rules).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn capture_backends_same_octets() -> Result<(), Box<dyn Error>> {
+        let rules = [localhost_rule(&[8080])];
+        let packets: Vec<Vec<u8>> = [(50000, 8080, 100), (8080, 50000, 1400), (50001, 22, 60)]
+            .iter()
+            .map(|&(src, dst, len)| {
+                let mut packet = tcp_packet(src, dst);
+                packet.resize(len, 0);
+                packet[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+                packet
+            })
+            .collect();
+
+        // AF_PACKET datagram socket: the kernel filters and delivers IP packets
+        let filter = compile_filter(&rules)?;
+        let mut af_packet = TransferCounter::new();
+        for packet in packets
+            .iter()
+            .filter(|p| bpf::tests::run(&filter, p, 1) != 0)
+        {
+            af_packet.add_packet(packet.len() as u64, packet);
+        }
+        assert_eq!(1500, af_packet.total);
+
+        // pcap files of the same packets, the tshark one only holding the
+        // packets matching its filter
+        let path = "/tmp/etsdiff_capture_backends_same_octets.pcap";
+        for (linktype, link_header) in [
+            (LINKTYPE_RAW, vec![]),
+            (
+                LINKTYPE_ETHERNET,
+                [[0u8; 12].as_slice(), &[0x08, 0x00]].concat(),
+            ),
+            (
+                LINKTYPE_LINUX_SLL,
+                [[0u8; 14].as_slice(), &[0x08, 0x00]].concat(),
+            ),
+            (
+                LINKTYPE_LINUX_SLL2,
+                [[0x08u8, 0x00].as_slice(), &[0u8; 18]].concat(),
+            ),
+        ] {
+            let frames: Vec<(u32, Vec<u8>)> = packets
+                .iter()
+                .map(|p| {
+                    let frame = [link_header.as_slice(), p].concat();
+                    (frame.len() as u32, frame)
+                })
+                .collect();
+            std::fs::write(path, pcap_file(linktype, &frames))?;
+            let pcap = pcap_transfer(path, &rules)?;
+            assert_eq!(af_packet, pcap, "linktype {linktype}");
+
+            let matching: Vec<(u32, Vec<u8>)> = frames
+                .into_iter()
+                .zip(&packets)
+                .filter(|(_, p)| bpf::tests::run(&filter, p, 1) != 0)
+                .map(|(frame, _)| frame)
+                .collect();
+            assert_eq!(2, matching.len());
+            let tshark = tshark_transfer(&pcap_file(linktype, &matching))?;
+            assert_eq!(af_packet, tshark, "linktype {linktype}");
+            // the former frame.len count
+            let frames_len: u64 = matching.iter().map(|(len, _)| u64::from(*len)).sum();
+            assert_eq!(
+                tshark.total + 2 * link_header.len() as u64,
+                frames_len,
+                "linktype {linktype}"
+            );
+        }
+        std::fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn tshark_capture_filter() -> Result<(), Box<dyn Error>> {
+        let mut web = localhost_rule(&[8080, 4326]);
+        assert_eq!(
+            "((host 127.0.0.1) and (port 8080 or port 4326))",
+            tshark_filter(&[web.clone()])?
+        );
+        assert_eq!(vec!["any"], tshark_interfaces(&[web.clone()]));
+
+        web.interface = Some("docker0".into());
+        let db = CaptureRule {
+            interface: Some("veth1".into()),
+            hosts: vec![parse_host("172.17.0.0/16")?, parse_host("::1")?],
+            ports: vec![5432],
+            filter: Some("tcp".into()),
+        };
+        let empty = localhost_rule(&[]);
+        let rules = [web, db, empty];
+        assert_eq!(
+            "((host 127.0.0.1) and (port 8080 or port 4326)) or \
+             ((net 172.17.0.0/16 or host ::1) and (port 5432) and (tcp))",
+            tshark_filter(&rules)?
+        );
+        assert_eq!(vec!["docker0", "veth1"], tshark_interfaces(&rules));
+
+        assert!(tshark_filter(&[localhost_rule(&[])]).is_err());
+
+        Ok(())
+    }
+}
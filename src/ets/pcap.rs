@@ -0,0 +1,406 @@
+// SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::error::Error;
+
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const PCAP_MAGIC_US: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NS: u32 = 0xa1b23c4d;
+const PCAPNG_SHB: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_IDB: u32 = 1;
+const PCAPNG_SPB: u32 = 3;
+const PCAPNG_EPB: u32 = 6;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPES_IP: [u16; 2] = [ETHERTYPE_IPV4, ETHERTYPE_IPV6];
+const ETHERTYPE_VLAN: [u16; 2] = [0x8100, 0x88a8];
+
+/// One captured frame, `data` may be truncated to the capture snaplen.
+pub struct Frame<'a> {
+    pub linktype: u32,
+    pub orig_len: u64,
+    pub data: &'a [u8],
+}
+
+impl Frame<'_> {
+    /// IPv4 or IPv6 packet carried by the frame (link layer header removed).
+    pub fn ip(&self) -> Option<&[u8]> {
+        let packet = match self.linktype {
+            // address family values depend on the OS, the IP version is checked
+            LINKTYPE_NULL => self.data.get(4..)?,
+            LINKTYPE_ETHERNET => {
+                let mut offset = 12;
+                let mut ethertype = be16(self.data, offset)?;
+                while ETHERTYPE_VLAN.contains(&ethertype) {
+                    offset += 4;
+                    ethertype = be16(self.data, offset)?;
+                }
+                if !ETHERTYPES_IP.contains(&ethertype) {
+                    return None;
+                }
+                &self.data[offset + 2..]
+            }
+            LINKTYPE_RAW | LINKTYPE_IPV4 => self.data,
+            LINKTYPE_LINUX_SLL => {
+                if !ETHERTYPES_IP.contains(&be16(self.data, 14)?) || self.data.len() < 16 {
+                    return None;
+                }
+                &self.data[16..]
+            }
+            LINKTYPE_LINUX_SLL2 => {
+                if !ETHERTYPES_IP.contains(&be16(self.data, 0)?) || self.data.len() < 20 {
+                    return None;
+                }
+                &self.data[20..]
+            }
+            _ => return None,
+        };
+        match packet.first() {
+            Some(b) if [4, 6].contains(&(b >> 4)) => Some(packet),
+            _ => None,
+        }
+    }
+    /// IP packet carried by the frame and its original length, link layer
+    /// header excluded (octets as counted by an AF_PACKET datagram socket).
+    pub fn ip_packet(&self) -> Option<(u64, &[u8])> {
+        let packet = self.ip()?;
+        let link_len = (self.data.len() - packet.len()) as u64;
+        Some((self.orig_len.saturating_sub(link_len), packet))
+    }
+}
+
+fn be16(data: &[u8], offset: usize) -> Option<u16> {
+    let b = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]))
+}
+
+// ===
+
+#[derive(Clone, Copy)]
+enum Endian {
+    Little,
+    Big,
+}
+
+struct Bytes<'a> {
+    data: &'a [u8],
+    endian: Endian,
+}
+
+impl<'a> Bytes<'a> {
+    fn u16(&self, offset: usize) -> Result<u16, Box<dyn Error>> {
+        let b: [u8; 2] = self.slice(offset, 2)?.try_into()?;
+        Ok(match self.endian {
+            Endian::Little => u16::from_le_bytes(b),
+            Endian::Big => u16::from_be_bytes(b),
+        })
+    }
+    fn u32(&self, offset: usize) -> Result<u32, Box<dyn Error>> {
+        let b: [u8; 4] = self.slice(offset, 4)?.try_into()?;
+        Ok(match self.endian {
+            Endian::Little => u32::from_le_bytes(b),
+            Endian::Big => u32::from_be_bytes(b),
+        })
+    }
+    fn slice(&self, offset: usize, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        match self.data.get(offset..offset + len) {
+            Some(s) => Ok(s),
+            None => bail!("Truncated capture file at offset {}", offset),
+        }
+    }
+}
+
+/// Call `f` for each frame of a pcap or pcapng capture.
+pub fn read_frames<F: FnMut(&Frame)>(data: &[u8], f: F) -> Result<(), Box<dyn Error>> {
+    if data.len() < 4 {
+        bail!("Not a capture file");
+    }
+    let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    if magic == PCAPNG_SHB {
+        return read_pcapng(data, f);
+    }
+    for endian in [Endian::Little, Endian::Big] {
+        let bytes = Bytes { data, endian };
+        if [PCAP_MAGIC_US, PCAP_MAGIC_NS].contains(&bytes.u32(0)?) {
+            return read_pcap(&bytes, f);
+        }
+    }
+    bail!("Not a capture file (unknown magic {:#x})", magic);
+}
+
+fn read_pcap<F: FnMut(&Frame)>(bytes: &Bytes, mut f: F) -> Result<(), Box<dyn Error>> {
+    let linktype = bytes.u32(20)? & 0x0fff_ffff;
+    let mut offset = 24;
+    while offset < bytes.data.len() {
+        let incl_len = bytes.u32(offset + 8)? as usize;
+        let orig_len = bytes.u32(offset + 12)?;
+        let data = bytes.slice(offset + 16, incl_len)?;
+        f(&Frame {
+            linktype,
+            orig_len: orig_len.into(),
+            data,
+        });
+        offset += 16 + incl_len;
+    }
+    Ok(())
+}
+
+fn read_pcapng<F: FnMut(&Frame)>(data: &[u8], mut f: F) -> Result<(), Box<dyn Error>> {
+    let mut bytes = Bytes {
+        data,
+        endian: Endian::Little,
+    };
+    // (linktype, snaplen) of the interfaces of the current section
+    let mut interfaces = Vec::<(u32, u32)>::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        if bytes.slice(offset, 4)? == PCAPNG_SHB.to_le_bytes() {
+            bytes.endian = Endian::Little;
+            if bytes.u32(offset + 8)? != PCAPNG_BYTE_ORDER_MAGIC {
+                bytes.endian = Endian::Big;
+            }
+            interfaces.clear();
+        }
+        let block_type = bytes.u32(offset)?;
+        let block_len = bytes.u32(offset + 4)? as usize;
+        if block_len < 12 || !block_len.is_multiple_of(4) {
+            bail!(
+                "Invalid pcapng block length {} at offset {}",
+                block_len,
+                offset
+            );
+        }
+        let body = offset + 8;
+        match block_type {
+            PCAPNG_IDB => {
+                interfaces.push((bytes.u16(body)?.into(), bytes.u32(body + 4)?));
+            }
+            PCAPNG_EPB => {
+                let interface = bytes.u32(body)? as usize;
+                let captured = bytes.u32(body + 12)? as usize;
+                let orig_len = bytes.u32(body + 16)?;
+                let Some(&(linktype, _)) = interfaces.get(interface) else {
+                    bail!("Unknown pcapng interface {}", interface);
+                };
+                f(&Frame {
+                    linktype,
+                    orig_len: orig_len.into(),
+                    data: bytes.slice(body + 20, captured)?,
+                });
+            }
+            PCAPNG_SPB => {
+                let Some(&(linktype, snaplen)) = interfaces.first() else {
+                    bail!("Unknown pcapng interface 0");
+                };
+                let Some(max_captured) = block_len.checked_sub(16) else {
+                    bail!(
+                        "Invalid pcapng block length {} at offset {}",
+                        block_len,
+                        offset
+                    );
+                };
+                let orig_len = bytes.u32(body)?;
+                let mut captured = (orig_len as usize).min(max_captured);
+                if snaplen != 0 {
+                    captured = captured.min(snaplen as usize);
+                }
+                f(&Frame {
+                    linktype,
+                    orig_len: orig_len.into(),
+                    data: bytes.slice(body + 4, captured)?,
+                });
+            }
+            _ => (),
+        }
+        offset += block_len;
+    }
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Classic little endian pcap file made of `frames` (orig_len, data).
+    pub fn pcap_file(linktype: u32, frames: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend(PCAP_MAGIC_US.to_le_bytes());
+        file.extend(2u16.to_le_bytes());
+        file.extend(4u16.to_le_bytes());
+        file.extend([0u8; 8]);
+        file.extend(65535u32.to_le_bytes());
+        file.extend(linktype.to_le_bytes());
+        for (orig_len, data) in frames {
+            file.extend([0u8; 8]);
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend(orig_len.to_le_bytes());
+            file.extend(data);
+        }
+        file
+    }
+
+    /// IPv4/TCP packet header from `src` to `dst` (both on localhost).
+    pub fn tcp_packet(src: u16, dst: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; 40];
+        packet[0] = 0x45;
+        packet[3] = 40;
+        packet[9] = 6;
+        packet[12..16].copy_from_slice(&[127, 0, 0, 1]);
+        packet[16..20].copy_from_slice(&[127, 0, 0, 1]);
+        packet[20..22].copy_from_slice(&src.to_be_bytes());
+        packet[22..24].copy_from_slice(&dst.to_be_bytes());
+        packet
+    }
+
+    // (linktype, orig_len, IPv4 packet) of each frame
+    type Frames = Vec<(u32, u64, Option<Vec<u8>>)>;
+
+    fn collect(data: &[u8]) -> Result<Frames, Box<dyn Error>> {
+        let mut frames = Vec::new();
+        read_frames(data, |frame| {
+            frames.push((
+                frame.linktype,
+                frame.orig_len,
+                frame.ip().map(|p| p.to_vec()),
+            ))
+        })?;
+        Ok(frames)
+    }
+
+    #[test]
+    fn pcap_read_frames() -> Result<(), Box<dyn Error>> {
+        let packet = tcp_packet(50000, 8080);
+        let file = pcap_file(
+            LINKTYPE_RAW,
+            &[
+                (1500, packet.clone()),
+                (60, vec![0x60; 40]),
+                (60, vec![0x00; 40]),
+            ],
+        );
+
+        let frames = collect(&file)?;
+        assert_eq!(3, frames.len());
+        assert_eq!((LINKTYPE_RAW, 1500, Some(packet)), frames[0]);
+        assert_eq!((LINKTYPE_RAW, 60, Some(vec![0x60; 40])), frames[1]);
+        // not IP
+        assert_eq!((LINKTYPE_RAW, 60, None), frames[2]);
+
+        // big endian
+        let mut file = Vec::new();
+        file.extend(PCAP_MAGIC_NS.to_be_bytes());
+        file.extend([0u8; 16]);
+        file.extend(LINKTYPE_RAW.to_be_bytes());
+        file.extend([0u8; 8]);
+        file.extend(2u32.to_be_bytes());
+        file.extend(100u32.to_be_bytes());
+        file.extend([0x45, 0]);
+        let frames = collect(&file)?;
+        assert_eq!(vec![(LINKTYPE_RAW, 100, Some(vec![0x45, 0]))], frames);
+
+        // truncated record
+        assert!(collect(&file[..file.len() - 1]).is_err());
+        assert!(collect(b"not a pcap").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pcap_linktypes() -> Result<(), Box<dyn Error>> {
+        let packet = tcp_packet(50000, 8080);
+        let mut ethernet = vec![0u8; 12];
+        ethernet.extend([0x81, 0x00, 0, 1, 0x08, 0x00]);
+        ethernet.extend(&packet);
+        let mut sll = vec![0u8; 14];
+        sll.extend([0x08, 0x00]);
+        sll.extend(&packet);
+        let mut sll2 = vec![0x08, 0x00];
+        sll2.extend([0u8; 18]);
+        sll2.extend(&packet);
+        let mut null = vec![2, 0, 0, 0];
+        null.extend(&packet);
+
+        for (linktype, frame) in [
+            (LINKTYPE_NULL, null),
+            (LINKTYPE_ETHERNET, ethernet),
+            (LINKTYPE_RAW, packet.clone()),
+            (LINKTYPE_LINUX_SLL, sll),
+            (LINKTYPE_LINUX_SLL2, sll2),
+        ] {
+            let file = pcap_file(linktype, &[(frame.len() as u32, frame)]);
+            let frames = collect(&file)?;
+            assert_eq!(Some(packet.clone()), frames[0].2, "linktype {linktype}");
+        }
+
+        // ARP over ethernet
+        let mut arp = vec![0u8; 12];
+        arp.extend([0x08, 0x06]);
+        arp.extend([0u8; 28]);
+        let file = pcap_file(LINKTYPE_ETHERNET, &[(42, arp)]);
+        assert_eq!(None, collect(&file)?[0].2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn pcapng_read_frames() -> Result<(), Box<dyn Error>> {
+        let packet = tcp_packet(8080, 50000);
+        let block = |block_type: u32, body: Vec<u8>| {
+            let len = (12 + body.len()) as u32;
+            let mut b = Vec::new();
+            b.extend(block_type.to_le_bytes());
+            b.extend(len.to_le_bytes());
+            b.extend(body);
+            b.extend(len.to_le_bytes());
+            b
+        };
+
+        let mut file = Vec::new();
+        let mut shb = PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        shb.extend([1, 0, 0, 0]);
+        shb.extend([0xff; 8]);
+        file.extend(block(PCAPNG_SHB, shb));
+        let mut idb = (LINKTYPE_RAW as u16).to_le_bytes().to_vec();
+        idb.extend([0, 0]);
+        idb.extend(0u32.to_le_bytes());
+        file.extend(block(PCAPNG_IDB, idb));
+        let mut epb = 0u32.to_le_bytes().to_vec();
+        epb.extend([0u8; 8]);
+        epb.extend((packet.len() as u32).to_le_bytes());
+        epb.extend(1000u32.to_le_bytes());
+        epb.extend(&packet);
+        file.extend(block(PCAPNG_EPB, epb));
+        let mut spb = (packet.len() as u32).to_le_bytes().to_vec();
+        spb.extend(&packet);
+        file.extend(block(PCAPNG_SPB, spb));
+        // unknown block skipped
+        file.extend(block(5, vec![0u8; 8]));
+
+        let frames = collect(&file)?;
+        assert_eq!(2, frames.len());
+        assert_eq!((LINKTYPE_RAW, 1000, Some(packet.clone())), frames[0]);
+        assert_eq!((LINKTYPE_RAW, 40, Some(packet)), frames[1]);
+
+        // simple packet block without its original length
+        file.extend(block(PCAPNG_SPB, Vec::new()));
+        assert_eq!(
+            format!(
+                "Invalid pcapng block length 12 at offset {}",
+                file.len() - 12
+            ),
+            collect(&file).unwrap_err().to_string()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,518 @@
+// SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::report::ProtocolReport;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+const DNS_PORT: u16 = 53;
+const DATABASE_PORTS: [(u16, &str); 5] = [
+    (5432, "postgresql"),
+    (3306, "mysql"),
+    (1433, "mssql"),
+    (27017, "mongodb"),
+    (6379, "redis"),
+];
+
+const HTTP_METHODS: [&[u8]; 9] = [
+    b"GET ",
+    b"POST ",
+    b"PUT ",
+    b"DELETE ",
+    b"HEAD ",
+    b"OPTIONS ",
+    b"PATCH ",
+    b"CONNECT ",
+    b"TRACE ",
+];
+const HTTP_RESPONSE: &[u8] = b"HTTP/";
+const HTTP_MAX_HEADERS: usize = 65_536;
+
+const TLS_HEADER_LEN: usize = 5;
+const TLS_HANDSHAKE: u8 = 22;
+const TLS_APPLICATION_DATA: u8 = 23;
+
+// ===
+
+/// One direction of a TCP connection.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+struct FlowKey {
+    src: IpAddr,
+    dst: IpAddr,
+    sport: u16,
+    dport: u16,
+}
+
+#[derive(Debug, PartialEq)]
+enum HttpState {
+    /// Waiting for a request or status line.
+    Idle,
+    /// Reading headers (already read ones kept to find the end and the length).
+    Headers { request: bool, read: Vec<u8> },
+    /// Body of known length.
+    Body(u64),
+    /// Body of unknown length (chunked or up to the connection end).
+    Stream,
+}
+
+#[derive(Debug, PartialEq)]
+enum Flow {
+    Unknown,
+    Http(HttpState),
+    Tls {
+        header: Vec<u8>,
+        record_type: u8,
+        remaining: usize,
+    },
+}
+
+/// Application level accounting of the captured packets: each TCP direction
+/// is followed to split HTTP headers and bodies, and TLS records.
+/// Packets are assumed in order, retransmissions are counted again.
+#[derive(Debug, Default, PartialEq)]
+pub struct ProtocolAnalyzer {
+    report: ProtocolReport,
+    flows: HashMap<FlowKey, Flow>,
+}
+
+impl ProtocolAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn report(&self) -> ProtocolReport {
+        self.report.clone()
+    }
+
+    /// Account a packet of `octets` (from the IP header), `packet` being its
+    /// captured bytes.
+    pub fn add(&mut self, octets: u64, packet: &[u8]) {
+        let Some(ip) = IpHeader::parse(packet) else {
+            self.report.other += octets;
+            return;
+        };
+        self.report.ip_headers += ip.header_len as u64;
+
+        let start = ip.header_len.min(packet.len());
+        let segment = &packet[start..ip.total_len.clamp(start, packet.len())];
+        let transport_len = ip.total_len.saturating_sub(ip.header_len);
+        let (header_len, sport, dport) = match (ip.protocol, segment) {
+            (IPPROTO_TCP, [s0, s1, d0, d1, _, _, _, _, _, _, _, _, offset, ..]) => (
+                usize::from(offset >> 4) * 4,
+                u16::from_be_bytes([*s0, *s1]),
+                u16::from_be_bytes([*d0, *d1]),
+            ),
+            (IPPROTO_UDP, [s0, s1, d0, d1, ..]) => (
+                8,
+                u16::from_be_bytes([*s0, *s1]),
+                u16::from_be_bytes([*d0, *d1]),
+            ),
+            _ => {
+                self.report.other += transport_len as u64;
+                return;
+            }
+        };
+        self.report.transport_headers += header_len.min(transport_len) as u64;
+        let payload_len = transport_len.saturating_sub(header_len) as u64;
+        self.report.payload += payload_len;
+        if payload_len == 0 {
+            return;
+        }
+        let payload = segment.get(header_len..).unwrap_or_default();
+
+        for (port, name) in DATABASE_PORTS {
+            if sport == port || dport == port {
+                *self.report.databases.entry(name.into()).or_default() += payload_len;
+            }
+        }
+
+        if ip.protocol == IPPROTO_UDP {
+            if sport == DNS_PORT || dport == DNS_PORT {
+                self.add_dns(payload_len, payload);
+            }
+            return;
+        }
+
+        let key = FlowKey {
+            src: ip.src,
+            dst: ip.dst,
+            sport,
+            dport,
+        };
+        let mut flow = self.flows.remove(&key).unwrap_or(Flow::Unknown);
+        if let Flow::Unknown = flow {
+            flow = detect_flow(payload);
+        }
+        match flow {
+            Flow::Http(ref mut state) => self.add_http(state, payload_len, payload),
+            Flow::Tls {
+                ref mut header,
+                ref mut record_type,
+                ref mut remaining,
+            } => self.add_tls(header, record_type, remaining, payload_len, payload),
+            Flow::Unknown => (),
+        }
+        self.flows.insert(key, flow);
+    }
+
+    fn add_dns(&mut self, payload_len: u64, payload: &[u8]) {
+        self.report.dns.octets += payload_len;
+        // QR bit of the flags
+        match payload.get(2) {
+            Some(flags) if flags & 0x80 != 0 => self.report.dns.responses += 1,
+            Some(_) => self.report.dns.queries += 1,
+            None => (),
+        }
+    }
+
+    fn add_http(&mut self, state: &mut HttpState, payload_len: u64, payload: &[u8]) {
+        let http = &mut self.report.http;
+        let mut remaining = payload_len;
+        let mut pos = 0;
+        while remaining > 0 {
+            let data = payload.get(pos..).unwrap_or_default();
+            if matches!(state, HttpState::Idle | HttpState::Stream) {
+                match http_message_start(data) {
+                    Some(request) => {
+                        if request {
+                            http.requests += 1;
+                        } else {
+                            http.responses += 1;
+                        }
+                        *state = HttpState::Headers {
+                            request,
+                            read: Vec::new(),
+                        };
+                    }
+                    None => {
+                        // continuation of a body (or garbage between messages)
+                        http.body += remaining;
+                        return;
+                    }
+                }
+            }
+
+            match state {
+                HttpState::Headers { request, read } => {
+                    let before = read.len();
+                    read.extend(&data[..data.len().min(HTTP_MAX_HEADERS)]);
+                    match find(read, b"\r\n\r\n") {
+                        Some(end) => {
+                            let used = (end + 4 - before) as u64;
+                            http.headers += used;
+                            remaining -= used.min(remaining);
+                            pos += used as usize;
+                            *state = http_body(*request, &read[..end]);
+                        }
+                        None => {
+                            http.headers += remaining;
+                            if read.len() >= HTTP_MAX_HEADERS {
+                                *state = HttpState::Stream;
+                            }
+                            return;
+                        }
+                    }
+                }
+                HttpState::Body(length) => {
+                    let used = (*length).min(remaining);
+                    http.body += used;
+                    remaining -= used;
+                    pos += used as usize;
+                    *length -= used;
+                    if *length == 0 {
+                        *state = HttpState::Idle;
+                    }
+                }
+                HttpState::Idle | HttpState::Stream => (),
+            }
+        }
+    }
+
+    fn add_tls(
+        &mut self,
+        header: &mut Vec<u8>,
+        record_type: &mut u8,
+        remaining: &mut usize,
+        payload_len: u64,
+        payload: &[u8],
+    ) {
+        let tls = &mut self.report.tls;
+        let mut left = payload_len as usize;
+        let mut pos = 0;
+        while left > 0 {
+            if *remaining > 0 {
+                let used = (*remaining).min(left);
+                if *record_type == TLS_APPLICATION_DATA {
+                    tls.application_data += used as u64;
+                } else {
+                    tls.handshake += used as u64;
+                }
+                *remaining -= used;
+                left -= used;
+                pos += used;
+                continue;
+            }
+
+            let used = (TLS_HEADER_LEN - header.len()).min(left);
+            match payload.get(pos..pos + used) {
+                Some(bytes) => header.extend(bytes),
+                None => {
+                    // not captured: record boundaries are lost
+                    tls.handshake += left as u64;
+                    return;
+                }
+            }
+            tls.record_headers += used as u64;
+            left -= used;
+            pos += used;
+            if header.len() == TLS_HEADER_LEN {
+                tls.records += 1;
+                *record_type = header[0];
+                *remaining = usize::from(u16::from_be_bytes([header[3], header[4]]));
+                header.clear();
+            }
+        }
+    }
+}
+
+fn detect_flow(payload: &[u8]) -> Flow {
+    if http_message_start(payload).is_some() {
+        return Flow::Http(HttpState::Idle);
+    }
+    if let [TLS_HANDSHAKE, 3, 0..=4, ..] = payload {
+        return Flow::Tls {
+            header: Vec::new(),
+            record_type: 0,
+            remaining: 0,
+        };
+    }
+    Flow::Unknown
+}
+
+/// `Some(true)` for a request line, `Some(false)` for a status line.
+fn http_message_start(data: &[u8]) -> Option<bool> {
+    if data.starts_with(HTTP_RESPONSE) {
+        return Some(false);
+    }
+    HTTP_METHODS
+        .iter()
+        .any(|m| data.starts_with(m))
+        .then_some(true)
+}
+
+fn http_body(request: bool, headers: &[u8]) -> HttpState {
+    let headers = String::from_utf8_lossy(headers).to_ascii_lowercase();
+    let mut lines = headers.split("\r\n");
+    let start = lines.next().unwrap_or_default();
+    let mut length = None;
+    let mut chunked = false;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim() {
+                "content-length" => length = value.trim().parse::<u64>().ok(),
+                "transfer-encoding" => chunked = value.contains("chunked"),
+                _ => (),
+            }
+        }
+    }
+
+    if chunked {
+        return HttpState::Stream;
+    }
+    match length {
+        Some(0) => HttpState::Idle,
+        Some(length) => HttpState::Body(length),
+        // requests without length have no body, as 1xx, 204 and 304 responses
+        None if request => HttpState::Idle,
+        None => {
+            let status = start.split(' ').nth(1).unwrap_or_default();
+            if status.starts_with('1') || status == "204" || status == "304" {
+                HttpState::Idle
+            } else {
+                HttpState::Stream
+            }
+        }
+    }
+}
+
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len()).position(|w| w == pattern)
+}
+
+// ===
+
+struct IpHeader {
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    header_len: usize,
+    total_len: usize,
+}
+
+impl IpHeader {
+    fn parse(packet: &[u8]) -> Option<Self> {
+        match packet.first()? >> 4 {
+            4 if packet.len() >= 20 => {
+                let header_len = usize::from(packet[0] & 0x0f) * 4;
+                // later fragments don't start with the transport header
+                let protocol = if u16::from_be_bytes([packet[6], packet[7]]) & 0x1fff != 0 {
+                    0
+                } else {
+                    packet[9]
+                };
+                let src: [u8; 4] = packet[12..16].try_into().ok()?;
+                let dst: [u8; 4] = packet[16..20].try_into().ok()?;
+                Some(Self {
+                    src: IpAddr::V4(Ipv4Addr::from(src)),
+                    dst: IpAddr::V4(Ipv4Addr::from(dst)),
+                    protocol,
+                    header_len,
+                    total_len: usize::from(u16::from_be_bytes([packet[2], packet[3]])),
+                })
+            }
+            6 if packet.len() >= 40 => {
+                let src: [u8; 16] = packet[8..24].try_into().ok()?;
+                let dst: [u8; 16] = packet[24..40].try_into().ok()?;
+                Some(Self {
+                    src: IpAddr::V6(Ipv6Addr::from(src)),
+                    dst: IpAddr::V6(Ipv6Addr::from(dst)),
+                    protocol: packet[6],
+                    header_len: 40,
+                    total_len: 40 + usize::from(u16::from_be_bytes([packet[4], packet[5]])),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; 40];
+        packet[0] = 0x45;
+        packet[2..4].copy_from_slice(&((40 + payload.len()) as u16).to_be_bytes());
+        packet[9] = IPPROTO_TCP;
+        packet[12..16].copy_from_slice(&[127, 0, 0, 1]);
+        packet[16..20].copy_from_slice(&[127, 0, 0, 1]);
+        packet[20..22].copy_from_slice(&sport.to_be_bytes());
+        packet[22..24].copy_from_slice(&dport.to_be_bytes());
+        packet[32] = 5 << 4;
+        packet.extend(payload);
+        packet
+    }
+
+    fn udp(sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = tcp(sport, dport, payload);
+        packet.drain(28..40);
+        packet[2..4].copy_from_slice(&((28 + payload.len()) as u16).to_be_bytes());
+        packet[9] = IPPROTO_UDP;
+        packet
+    }
+
+    fn add(analyzer: &mut ProtocolAnalyzer, packet: &[u8]) {
+        analyzer.add(packet.len() as u64, packet);
+    }
+
+    #[test]
+    fn protocol_layers() {
+        let mut analyzer = ProtocolAnalyzer::new();
+        add(&mut analyzer, &tcp(50000, 8080, b""));
+        add(&mut analyzer, &tcp(50000, 8080, b"0123456789"));
+        add(&mut analyzer, &udp(50000, 9999, b"0123"));
+        // truncated capture of a 986 octets packet
+        let packet = tcp(50000, 8080, &[0u8; 986 - 40]);
+        analyzer.add(986, &packet[..100]);
+
+        let report = analyzer.report();
+        assert_eq!(4 * 20, report.ip_headers);
+        assert_eq!(3 * 20 + 8, report.transport_headers);
+        assert_eq!(10 + 4 + 946, report.payload);
+        assert_eq!(0, report.other);
+    }
+
+    #[test]
+    fn protocol_http() {
+        let mut analyzer = ProtocolAnalyzer::new();
+        let request = b"GET /index HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        add(&mut analyzer, &tcp(50000, 8080, request));
+        // headers and body split over segments
+        add(
+            &mut analyzer,
+            &tcp(8080, 50000, b"HTTP/1.1 200 OK\r\nContent-"),
+        );
+        add(&mut analyzer, &tcp(8080, 50000, b"Length: 10\r\n\r\n01234"));
+        add(&mut analyzer, &tcp(8080, 50000, b"56789"));
+        // keep-alive: a POST and a chunked response
+        let post = b"POST /form HTTP/1.1\r\nContent-Length: 4\r\n\r\nname";
+        add(&mut analyzer, &tcp(50000, 8080, post));
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n";
+        add(&mut analyzer, &tcp(8080, 50000, chunked));
+        add(&mut analyzer, &tcp(8080, 50000, b"0\r\n\r\n"));
+        let not_modified = b"GET /index HTTP/1.1\r\n\r\n";
+        add(&mut analyzer, &tcp(50000, 8080, not_modified));
+        add(
+            &mut analyzer,
+            &tcp(8080, 50000, b"HTTP/1.1 304 Not Modified\r\n\r\n"),
+        );
+
+        let http = analyzer.report().http;
+        assert_eq!(3, http.requests);
+        assert_eq!(3, http.responses);
+        let headers =
+            request.len() + b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n".len() + post.len() - 4
+                + b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".len()
+                + not_modified.len()
+                + b"HTTP/1.1 304 Not Modified\r\n\r\n".len();
+        assert_eq!(headers as u64, http.headers);
+        assert_eq!((10 + 4 + 10 + 5) as u64, http.body);
+    }
+
+    #[test]
+    fn protocol_tls() {
+        let mut analyzer = ProtocolAnalyzer::new();
+        // client hello of 6 octets, then 2 application data records, the
+        // second one starting in the same segment
+        add(
+            &mut analyzer,
+            &tcp(50000, 443, &[22, 3, 1, 0, 6, 1, 0, 0, 2, 3, 3]),
+        );
+        let mut data = vec![23, 3, 3, 0, 4, 0xaa, 0xbb, 0xcc, 0xdd, 23, 3];
+        add(&mut analyzer, &tcp(50000, 443, &data));
+        data = vec![3, 0, 2, 0xee, 0xff];
+        add(&mut analyzer, &tcp(50000, 443, &data));
+
+        let tls = analyzer.report().tls;
+        assert_eq!(3, tls.records);
+        assert_eq!(15, tls.record_headers);
+        assert_eq!(6, tls.handshake);
+        assert_eq!(6, tls.application_data);
+    }
+
+    #[test]
+    fn protocol_dns_and_databases() {
+        let mut analyzer = ProtocolAnalyzer::new();
+        let query = [0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        let response = [0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0, 0xc0];
+        add(&mut analyzer, &udp(50000, 53, &query));
+        add(&mut analyzer, &udp(53, 50000, &response));
+        add(&mut analyzer, &tcp(50000, 5432, b"Q\0\0\0\x0dSELECT 1\0"));
+        add(&mut analyzer, &tcp(6379, 50000, b"+OK\r\n"));
+
+        let report = analyzer.report();
+        assert_eq!(1, report.dns.queries);
+        assert_eq!(1, report.dns.responses);
+        assert_eq!(25, report.dns.octets);
+        assert_eq!(14, report.databases["postgresql"]);
+        assert_eq!(5, report.databases["redis"]);
+        assert!(!report.databases.contains_key("mysql"));
+    }
+}
@@ -4,73 +4,509 @@
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use stats::median;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+
+use super::impact::{ImpactModel, ImpactReport};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct TestReport {
     name: String,
+    /// Warm-up run, not counted in the total.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub warmup: bool,
     pub energy: f64,
     pub transfer: u64,
     pub storage: u64,
+    /// Seconds since the campaign start (monotonic clock).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// Conditions after the cooldown, right before the test.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_test: Option<Conditions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub functional_units: Option<FunctionalUnits>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_apparent: Option<u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub energy_by_service: BTreeMap<String, f64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub transfer_by_service: BTreeMap<String, ServiceTransfer>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocols: Option<ProtocolReport>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage_by_service: BTreeMap<String, ServiceStorage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_changes: Option<StorageChanges>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline_corrected: Option<CorrectedReport>,
 }
 
 impl TestReport {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
     pub fn new(name: &str) -> Self {
         Self {
             name: name.into(),
+            warmup: false,
             energy: 0.0,
             transfer: 0,
             storage: 0,
+            start: None,
+            end: None,
+            duration: None,
+            pre_test: None,
+            functional_units: None,
+            storage_apparent: None,
+            energy_by_service: BTreeMap::new(),
+            transfer_by_service: BTreeMap::new(),
+            protocols: None,
+            storage_by_service: BTreeMap::new(),
+            storage_changes: None,
+            metrics: BTreeMap::new(),
+            baseline_corrected: None,
+        }
+    }
+}
+
+// ===
+
+/// System state measured by a cooldown. Values the system doesn't give are
+/// missing.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct Conditions {
+    /// Seconds spent in the cooldown.
+    pub waited: f64,
+    /// False when the timeout was reached before the thresholds.
+    pub quiet: bool,
+    /// Busy part of the CPU time (0 to 1).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_load: Option<f64>,
+    /// Package temperature (°C).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// Package power (W).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power: Option<f64>,
+}
+
+// ===
+
+/// Values per work unit of a test (requests, transactions...) and throughput
+/// (units per second).
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct FunctionalUnits {
+    pub count: u64,
+    pub energy_per_unit: f64,
+    pub transfer_per_unit: f64,
+    pub throughput: f64,
+}
+
+impl FunctionalUnits {
+    pub fn new(count: u64, tr: &TestReport) -> Self {
+        let per_unit = |value: f64| if count > 0 { value / count as f64 } else { 0.0 };
+        let throughput = match tr.duration {
+            Some(duration) if duration > 0.0 => count as f64 / duration,
+            _ => 0.0,
+        };
+        Self {
+            count,
+            energy_per_unit: per_unit(tr.energy),
+            transfer_per_unit: per_unit(tr.transfer as f64),
+            throughput,
+        }
+    }
+}
+
+// ===
+
+/// Octets received by (ingress) and sent from (egress) the ports of a service.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+pub struct ServiceTransfer {
+    pub ingress: u64,
+    pub egress: u64,
+}
+
+// ===
+
+/// Storage of a service: size of its storage paths and sum of its storage
+/// probes (logical sizes given by commands), in octets. When the service has
+/// include/exclude patterns, `files` lists the files measured in its paths.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+pub struct ServiceStorage {
+    pub paths: u64,
+    pub probes: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+}
+
+// ===
+
+/// Storage changes made by a test: `delta` of size (octets) and number of
+/// files created, modified and deleted.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct StorageChanges {
+    pub delta: i64,
+    pub created: u64,
+    pub modified: u64,
+    pub deleted: u64,
+}
+
+// ===
+
+/// Transfer split by protocol layer (octets) and application messages.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct ProtocolReport {
+    pub ip_headers: u64,
+    pub transport_headers: u64,
+    pub payload: u64,
+    /// Neither TCP nor UDP (or later IP fragments).
+    pub other: u64,
+    #[serde(default, skip_serializing_if = "HttpReport::is_empty")]
+    pub http: HttpReport,
+    #[serde(default, skip_serializing_if = "TlsReport::is_empty")]
+    pub tls: TlsReport,
+    #[serde(default, skip_serializing_if = "DnsReport::is_empty")]
+    pub dns: DnsReport,
+    /// Payload octets from or to well-known database ports.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub databases: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct HttpReport {
+    pub requests: u64,
+    pub responses: u64,
+    pub headers: u64,
+    pub body: u64,
+}
+
+impl HttpReport {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// TLS records: `record_headers` and `handshake` (any record but application
+/// data) are the TLS overhead.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct TlsReport {
+    pub records: u64,
+    pub record_headers: u64,
+    pub handshake: u64,
+    pub application_data: u64,
+}
+
+impl TlsReport {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct DnsReport {
+    pub queries: u64,
+    pub responses: u64,
+    pub octets: u64,
+}
+
+impl DnsReport {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl ProtocolReport {
+    /// Median of each value of `reports`.
+    pub fn median(reports: &[&ProtocolReport]) -> Self {
+        let m = |value: &dyn Fn(&ProtocolReport) -> u64| -> u64 {
+            median(reports.iter().map(|r| value(r) as f64)).unwrap_or(0.0) as u64
+        };
+        let mut databases = BTreeMap::new();
+        for name in reports.iter().flat_map(|r| r.databases.keys()) {
+            if !databases.contains_key(name) {
+                let octets = m(&|r| r.databases.get(name).copied().unwrap_or(0));
+                databases.insert(name.clone(), octets);
+            }
+        }
+        Self {
+            ip_headers: m(&|r| r.ip_headers),
+            transport_headers: m(&|r| r.transport_headers),
+            payload: m(&|r| r.payload),
+            other: m(&|r| r.other),
+            http: HttpReport {
+                requests: m(&|r| r.http.requests),
+                responses: m(&|r| r.http.responses),
+                headers: m(&|r| r.http.headers),
+                body: m(&|r| r.http.body),
+            },
+            tls: TlsReport {
+                records: m(&|r| r.tls.records),
+                record_headers: m(&|r| r.tls.record_headers),
+                handshake: m(&|r| r.tls.handshake),
+                application_data: m(&|r| r.tls.application_data),
+            },
+            dns: DnsReport {
+                queries: m(&|r| r.dns.queries),
+                responses: m(&|r| r.dns.responses),
+                octets: m(&|r| r.dns.octets),
+            },
+            databases,
         }
     }
 }
 
 // ===
 
+/// Values of a test once the idle consumption of its services is removed.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct CorrectedReport {
+    pub energy: f64,
+    pub transfer: u64,
+    /// Storage change of the test without the idle drift, when measured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_delta: Option<i64>,
+}
+
+// ===
+
+/// Idle measurement of the services of a test over `duration` seconds.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct BaselineReport {
+    name: String,
+    pub duration: f64,
+    pub energy: f64,
+    pub transfer: u64,
+    /// Storage change during the idle measurement.
+    pub storage_drift: i64,
+}
+
+impl BaselineReport {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            duration: 0.0,
+            energy: 0.0,
+            transfer: 0,
+            storage_drift: 0,
+        }
+    }
+    /// Remove from `tr` the idle values expected during `duration` seconds.
+    pub fn correct(&self, tr: &TestReport, duration: f64) -> CorrectedReport {
+        let ratio = if self.duration > 0.0 {
+            duration / self.duration
+        } else {
+            0.0
+        };
+        CorrectedReport {
+            energy: (tr.energy - self.energy * ratio).max(0.0),
+            transfer: tr
+                .transfer
+                .saturating_sub((self.transfer as f64 * ratio) as u64),
+            storage_delta: tr
+                .storage_changes
+                .as_ref()
+                .map(|c| c.delta - (self.storage_drift as f64 * ratio) as i64),
+        }
+    }
+}
+
+// ===
+
+/// Order the tests were run in, with the seed giving it again for randomized
+/// schedulers.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+pub struct Schedule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    pub order: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Report {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schedule: Option<Schedule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    baseline: Vec<BaselineReport>,
     details: Vec<TestReport>,
     total: Vec<TestReport>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    impact: Vec<ImpactReport>,
+    #[serde(skip)]
+    impact_model: Option<ImpactModel>,
+    /// Unit of the metrics that have one.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    units: BTreeMap<String, String>,
 }
 
 impl Report {
     pub fn new() -> Self {
         Self {
+            schedule: None,
+            baseline: Vec::new(),
             details: Vec::new(),
             total: Vec::new(),
+            impact: Vec::new(),
+            impact_model: None,
+            units: BTreeMap::new(),
         }
     }
+    /// Footprint of the totals, computed with the totals.
+    pub fn set_impact_model(&mut self, model: ImpactModel) {
+        self.impact_model = Some(model);
+    }
+    pub fn impact_model(&self) -> Option<&ImpactModel> {
+        self.impact_model.as_ref()
+    }
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = Some(schedule);
+    }
+    pub fn schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+    pub fn set_unit(&mut self, metric: &str, unit: &str) {
+        self.units.insert(metric.into(), unit.into());
+    }
+    pub fn units(&self) -> &BTreeMap<String, String> {
+        &self.units
+    }
+    pub fn add_baseline_report(&mut self, br: BaselineReport) {
+        self.baseline.push(br);
+    }
+    pub fn baseline_report(&self, name: &str) -> Option<&BaselineReport> {
+        self.baseline.iter().find(|br| br.name == name)
+    }
     pub fn add_test_report(&mut self, ir: TestReport) {
         self.details.push(ir);
     }
+    pub fn details(&self) -> impl Iterator<Item = &TestReport> {
+        self.details.iter()
+    }
     pub fn compute_total(&mut self) {
         self.total = Vec::new();
-        let mut tr_dict_e = HashMap::<String, Vec<f64>>::new();
-        let mut tr_dict_t = HashMap::<String, Vec<f64>>::new();
-        let mut tr_dict_s = HashMap::<String, Vec<f64>>::new();
-
-        for tr in &self.details {
-            if !tr_dict_e.contains_key(&tr.name) {
-                tr_dict_e.insert(String::from(&tr.name), vec![tr.energy]);
-                tr_dict_t.insert(String::from(&tr.name), vec![tr.transfer as f64]);
-                tr_dict_s.insert(String::from(&tr.name), vec![tr.storage as f64]);
-            } else {
-                tr_dict_e.get_mut(&tr.name).unwrap().push(tr.energy);
-                tr_dict_s
-                    .get_mut(&tr.name)
-                    .unwrap()
-                    .push(tr.transfer as f64);
-                tr_dict_s.get_mut(&tr.name).unwrap().push(tr.storage as f64);
-            }
+        let mut tr_dict = HashMap::<String, Vec<&TestReport>>::new();
+
+        for tr in self.details.iter().filter(|tr| !tr.warmup) {
+            tr_dict.entry(String::from(&tr.name)).or_default().push(tr);
         }
-        for key in tr_dict_e.keys().sorted() {
+        for key in tr_dict.keys().sorted() {
+            let trs = &tr_dict[key];
             let mut tr = TestReport::new(key);
-            tr.energy = median(tr_dict_e[key].clone().into_iter()).unwrap();
-            tr.transfer = median(tr_dict_t[key].clone().into_iter()).unwrap() as u64;
-            tr.storage = median(tr_dict_s[key].clone().into_iter()).unwrap() as u64;
+            tr.energy = median(trs.iter().map(|t| t.energy)).unwrap();
+            tr.transfer = median(trs.iter().map(|t| t.transfer as f64)).unwrap() as u64;
+            tr.storage = median(trs.iter().map(|t| t.storage as f64)).unwrap() as u64;
+            tr.duration = median(trs.iter().filter_map(|t| t.duration));
+            // from the total values
+            tr.functional_units = trs[0]
+                .functional_units
+                .as_ref()
+                .map(|fu| FunctionalUnits::new(fu.count, &tr));
+
+            let mut by_service = BTreeMap::<String, Vec<f64>>::new();
+            for t in trs {
+                for (service, energy) in &t.energy_by_service {
+                    by_service.entry(service.clone()).or_default().push(*energy);
+                }
+            }
+            for (service, energies) in by_service {
+                tr.energy_by_service
+                    .insert(service, median(energies.into_iter()).unwrap());
+            }
+
+            let mut by_service = BTreeMap::<String, Vec<&ServiceTransfer>>::new();
+            for t in trs {
+                for (service, transfer) in &t.transfer_by_service {
+                    by_service
+                        .entry(service.clone())
+                        .or_default()
+                        .push(transfer);
+                }
+            }
+            for (service, transfers) in by_service {
+                let st = ServiceTransfer {
+                    ingress: median(transfers.iter().map(|t| t.ingress as f64)).unwrap() as u64,
+                    egress: median(transfers.iter().map(|t| t.egress as f64)).unwrap() as u64,
+                };
+                tr.transfer_by_service.insert(service, st);
+            }
+
+            let mut by_service = BTreeMap::<String, Vec<&ServiceStorage>>::new();
+            for t in trs {
+                for (service, storage) in &t.storage_by_service {
+                    by_service.entry(service.clone()).or_default().push(storage);
+                }
+            }
+            for (service, storages) in by_service {
+                let ss = ServiceStorage {
+                    paths: median(storages.iter().map(|s| s.paths as f64)).unwrap() as u64,
+                    probes: median(storages.iter().map(|s| s.probes as f64)).unwrap() as u64,
+                    // files of the last iteration
+                    files: storages.last().unwrap().files.clone(),
+                };
+                tr.storage_by_service.insert(service, ss);
+            }
+
+            let apparent: Vec<f64> = trs
+                .iter()
+                .filter_map(|t| t.storage_apparent.map(|a| a as f64))
+                .collect();
+            tr.storage_apparent = median(apparent.into_iter()).map(|a| a as u64);
+
+            let changes: Vec<&StorageChanges> = trs
+                .iter()
+                .filter_map(|t| t.storage_changes.as_ref())
+                .collect();
+            if !changes.is_empty() {
+                let m = |value: &dyn Fn(&StorageChanges) -> f64| {
+                    median(changes.iter().map(|c| value(c))).unwrap()
+                };
+                tr.storage_changes = Some(StorageChanges {
+                    delta: m(&|c| c.delta as f64) as i64,
+                    created: m(&|c| c.created as f64) as u64,
+                    modified: m(&|c| c.modified as f64) as u64,
+                    deleted: m(&|c| c.deleted as f64) as u64,
+                });
+            }
+
+            let mut metrics = BTreeMap::<String, Vec<f64>>::new();
+            for t in trs {
+                for (name, value) in &t.metrics {
+                    metrics.entry(name.clone()).or_default().push(*value);
+                }
+            }
+            for (name, values) in metrics {
+                tr.metrics.insert(name, median(values.into_iter()).unwrap());
+            }
+
+            let protocols: Vec<&ProtocolReport> =
+                trs.iter().filter_map(|t| t.protocols.as_ref()).collect();
+            if !protocols.is_empty() {
+                tr.protocols = Some(ProtocolReport::median(&protocols));
+            }
+
+            let corrected: Option<Vec<&CorrectedReport>> =
+                trs.iter().map(|t| t.baseline_corrected.as_ref()).collect();
+            if let Some(corrected) = corrected {
+                tr.baseline_corrected = Some(CorrectedReport {
+                    energy: median(corrected.iter().map(|c| c.energy)).unwrap(),
+                    transfer: median(corrected.iter().map(|c| c.transfer as f64)).unwrap() as u64,
+                    storage_delta: median(corrected.iter().filter_map(|c| c.storage_delta))
+                        .map(|d| d as i64),
+                });
+            }
 
             self.total.push(tr);
         }
+
+        if let Some(ref model) = self.impact_model {
+            self.impact = self.total.iter().map(|tr| model.impact(tr)).collect();
+        }
     }
 }
 
@@ -160,4 +596,340 @@ total:
 
         Ok(())
     }
+
+    #[test]
+    fn test_report_warmup() -> Result<(), serde_yaml::Error> {
+        let mut r = Report::new();
+
+        for (energy, warmup) in [(100.0, true), (1.0, false), (3.0, false)] {
+            let mut tr = TestReport::new("Test 1");
+            tr.energy = energy;
+            tr.warmup = warmup;
+            r.add_test_report(tr);
+        }
+
+        r.compute_total();
+
+        let yaml = serde_yaml::to_string(&r)?;
+        assert!(yaml.starts_with("details:\n- name: Test 1\n  warmup: true\n  energy: 100.0\n"));
+        assert!(yaml.contains("- name: Test 1\n  energy: 1.0\n"));
+        assert!(
+            yaml.ends_with("total:\n- name: Test 1\n  energy: 2.0\n  transfer: 0\n  storage: 0\n")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_impact() -> Result<(), serde_yaml::Error> {
+        let mut r = Report::new();
+        r.set_impact_model(ImpactModel::new(100.0, 1.0, 0.0));
+
+        for energy in [3_600.0, 7_200.0, 10_800.0] {
+            let mut tr = TestReport::new("Test 1");
+            tr.energy = energy;
+            tr.transfer = 1_000_000;
+            r.add_test_report(tr);
+        }
+
+        r.compute_total();
+
+        let yaml = serde_yaml::to_string(&r)?;
+        assert!(yaml.ends_with(
+            "impact:
+- name: Test 1
+  energy_kwh: 0.002
+  network_kwh: 0.001
+  storage_kwh: 0.0
+  total_kwh: 0.003
+  primary_kwh: 0.003
+  carbon: 0.3
+"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_baseline_report() -> Result<(), serde_yaml::Error> {
+        let mut br = BaselineReport::new("Test 1");
+        br.duration = 10.0;
+        br.energy = 5.0;
+        br.transfer = 1_000;
+        br.storage_drift = 200;
+
+        let mut tr = TestReport::new("Test 1");
+        tr.energy = 4.0;
+        tr.transfer = 3_000;
+        tr.storage = 10_000;
+
+        // no storage change measured
+        assert!(br.correct(&tr, 2.0).storage_delta.is_none());
+
+        tr.storage_changes = Some(StorageChanges {
+            delta: 500,
+            ..Default::default()
+        });
+
+        // the drift is applied to the change, not to the size
+        let corrected = br.correct(&tr, 2.0);
+        assert_eq!(3.0, corrected.energy);
+        assert_eq!(2_800, corrected.transfer);
+        assert_eq!(Some(460), corrected.storage_delta);
+
+        // idle can't make a test negative, but storage can shrink
+        let corrected = br.correct(&tr, 100.0);
+        assert_eq!(0.0, corrected.energy);
+        assert_eq!(0, corrected.transfer);
+        assert_eq!(Some(-1_500), corrected.storage_delta);
+
+        let mut r = Report::new();
+        r.add_baseline_report(br);
+        assert!(r.baseline_report("Test 1").is_some());
+        assert!(r.baseline_report("Test 2").is_none());
+
+        tr.baseline_corrected = Some(r.baseline_report("Test 1").unwrap().correct(&tr, 2.0));
+        r.add_test_report(tr);
+        r.compute_total();
+
+        let yaml = serde_yaml::to_string(&r)?;
+        let expected_yaml: &str = "baseline:
+- name: Test 1
+  duration: 10.0
+  energy: 5.0
+  transfer: 1000
+  storage_drift: 200
+details:
+- name: Test 1
+  energy: 4.0
+  transfer: 3000
+  storage: 10000
+  storage_changes:
+    delta: 500
+    created: 0
+    modified: 0
+    deleted: 0
+  baseline_corrected:
+    energy: 3.0
+    transfer: 2800
+    storage_delta: 460
+total:
+- name: Test 1
+  energy: 4.0
+  transfer: 3000
+  storage: 10000
+  storage_changes:
+    delta: 500
+    created: 0
+    modified: 0
+    deleted: 0
+  baseline_corrected:
+    energy: 3.0
+    transfer: 2800
+    storage_delta: 460
+";
+        assert_eq!(expected_yaml, yaml);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_energy_by_service() -> Result<(), serde_yaml::Error> {
+        let mut r = Report::new();
+
+        for (e1, e2, transfer) in [(1.0, 4.0, 10), (2.0, 6.0, 30), (9.0, 5.0, 20)] {
+            let mut tr = TestReport::new("Test 1");
+            tr.energy = e1 + e2;
+            tr.transfer = transfer;
+            tr.energy_by_service.insert("Service 1".into(), e1);
+            tr.energy_by_service.insert("Service 2".into(), e2);
+            r.add_test_report(tr);
+        }
+
+        r.compute_total();
+
+        assert_eq!(1, r.total.len());
+        let total = &r.total[0];
+        assert_eq!(8.0, total.energy);
+        assert_eq!(20, total.transfer);
+        assert_eq!(2.0, total.energy_by_service["Service 1"]);
+        assert_eq!(5.0, total.energy_by_service["Service 2"]);
+
+        let yaml = serde_yaml::to_string(total)?;
+        assert_eq!(
+            yaml,
+            "name: Test 1
+energy: 8.0
+transfer: 20
+storage: 0
+energy_by_service:
+  Service 1: 2.0
+  Service 2: 5.0
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_transfer_by_service() -> Result<(), serde_yaml::Error> {
+        let mut r = Report::new();
+
+        for (ingress, egress) in [(100, 1000), (120, 900), (80, 1100)] {
+            let mut tr = TestReport::new("Test 1");
+            tr.transfer = ingress + egress;
+            tr.transfer_by_service
+                .insert("Web".into(), ServiceTransfer { ingress, egress });
+            r.add_test_report(tr);
+        }
+
+        r.compute_total();
+
+        let total = &r.total[0];
+        assert_eq!(
+            ServiceTransfer {
+                ingress: 100,
+                egress: 1000
+            },
+            total.transfer_by_service["Web"]
+        );
+
+        let yaml = serde_yaml::to_string(total)?;
+        assert_eq!(
+            yaml,
+            "name: Test 1
+energy: 0.0
+transfer: 1100
+storage: 0
+transfer_by_service:
+  Web:
+    ingress: 100
+    egress: 1000
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_functional_units() -> Result<(), serde_yaml::Error> {
+        let mut r = Report::new();
+
+        for (energy, transfer, duration) in
+            [(10.0, 4000, 2.0), (30.0, 2000, 8.0), (20.0, 3000, 4.0)]
+        {
+            let mut tr = TestReport::new("Test 1");
+            tr.energy = energy;
+            tr.transfer = transfer;
+            tr.duration = Some(duration);
+            tr.functional_units = Some(FunctionalUnits::new(100, &tr));
+            r.add_test_report(tr);
+        }
+
+        assert_eq!(
+            Some(FunctionalUnits {
+                count: 100,
+                energy_per_unit: 0.1,
+                transfer_per_unit: 40.0,
+                throughput: 50.0
+            }),
+            r.details[0].functional_units
+        );
+
+        r.compute_total();
+
+        let yaml = serde_yaml::to_string(&r.total[0])?;
+        assert_eq!(
+            yaml,
+            "name: Test 1
+energy: 20.0
+transfer: 3000
+storage: 0
+duration: 4.0
+functional_units:
+  count: 100
+  energy_per_unit: 0.2
+  transfer_per_unit: 30.0
+  throughput: 25.0
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_metrics() -> Result<(), serde_yaml::Error> {
+        let mut r = Report::new();
+
+        for (memory, cpu) in [(2048.0, 1.5), (1024.0, 0.5), (4096.0, 1.0)] {
+            let mut tr = TestReport::new("Test 1");
+            tr.metrics.insert("memory".into(), memory);
+            tr.metrics.insert("cpu".into(), cpu);
+            r.add_test_report(tr);
+        }
+
+        r.compute_total();
+
+        let yaml = serde_yaml::to_string(&r.total[0])?;
+        assert_eq!(
+            yaml,
+            "name: Test 1
+energy: 0.0
+transfer: 0
+storage: 0
+metrics:
+  cpu: 1.0
+  memory: 2048.0
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_protocols() -> Result<(), serde_yaml::Error> {
+        let mut r = Report::new();
+
+        for (payload, requests, postgresql) in [(100, 1, 10), (300, 3, 30), (200, 2, 0)] {
+            let mut tr = TestReport::new("Test 1");
+            let mut protocols = ProtocolReport {
+                ip_headers: 40,
+                transport_headers: 40,
+                payload,
+                ..Default::default()
+            };
+            protocols.http.requests = requests;
+            if postgresql > 0 {
+                protocols.databases.insert("postgresql".into(), postgresql);
+            }
+            tr.protocols = Some(protocols);
+            r.add_test_report(tr);
+        }
+
+        r.compute_total();
+
+        let protocols = r.total[0].protocols.as_ref().unwrap();
+        assert_eq!(200, protocols.payload);
+        assert_eq!(2, protocols.http.requests);
+        assert_eq!(10, protocols.databases["postgresql"]);
+
+        let yaml = serde_yaml::to_string(protocols)?;
+        assert_eq!(
+            yaml,
+            "ip_headers: 40
+transport_headers: 40
+payload: 200
+other: 0
+http:
+  requests: 2
+  responses: 0
+  headers: 0
+  body: 0
+databases:
+  postgresql: 10
+"
+        );
+
+        Ok(())
+    }
 }
@@ -7,6 +7,9 @@ use std::error::Error;
 use std::rc::Rc;
 use sysinfo::{System, SystemExt};
 
+use super::cgroup::CGROUP_ROOT;
+use super::packet_capture::CaptureSettings;
+use super::storage::StorageFilter;
 use super::system_call::SystemCall;
 
 pub type ServicesLink = Rc<RefCell<Vec<Service>>>;
@@ -18,10 +21,13 @@ pub struct Service {
     pub process_name: Option<String>,
     cgroup: Option<Cgroup>,
     pub ports: Vec<u32>,
+    pub capture: CaptureSettings,
     pub prepare: Option<SystemCall>,
     pub clean: Option<SystemCall>,
     pub release: Option<SystemCall>,
     pub storage_paths: Vec<String>,
+    pub storage_filter: StorageFilter,
+    pub storage_probes: Vec<SystemCall>,
 }
 
 impl Service {
@@ -31,10 +37,13 @@ impl Service {
             process_name: None,
             cgroup: None,
             ports: Vec::new(),
+            capture: CaptureSettings::default(),
             prepare: None,
             clean: None,
             release: None,
             storage_paths: Vec::new(),
+            storage_filter: StorageFilter::default(),
+            storage_probes: Vec::new(),
         }
     }
 
@@ -50,6 +59,28 @@ impl Service {
         self.storage_paths.push(path.into());
     }
 
+    /// Only files matching an include pattern are measured in storage paths.
+    pub fn add_storage_include(&mut self, pattern: &str) -> Result<(), Box<dyn Error>> {
+        self.storage_filter.add_include(pattern)
+    }
+
+    /// Files and directories matching an exclude pattern are skipped.
+    pub fn add_storage_exclude(&mut self, pattern: &str) -> Result<(), Box<dyn Error>> {
+        self.storage_filter.add_exclude(pattern)
+    }
+
+    /// Command printing a size in octets on its standard output (first
+    /// number found), like `du -sb /path` or a database size query.
+    pub fn add_storage_probe(&mut self, command_line: &str) {
+        self.storage_probes.push(SystemCall::new(command_line));
+    }
+
+    pub fn cgroup_path(&self) -> Option<String> {
+        self.process_name
+            .as_ref()
+            .map(|pname| format!("{CGROUP_ROOT}/{pname}"))
+    }
+
     pub fn prepare(&mut self) -> Result<(), Box<dyn Error>> {
         if let Some(ref mut sc) = self.prepare {
             sc.execute()?;
@@ -130,6 +161,7 @@ mod tests {
         assert!(s.clean.is_none());
         assert!(s.release.is_none());
         assert_eq!(0, s.storage_paths.len());
+        assert_eq!(0, s.storage_probes.len());
     }
 
     #[test]
@@ -144,6 +176,20 @@ mod tests {
         assert_eq!("pidof", s.process_name.unwrap());
     }
 
+    #[test]
+    fn service_cgroup_path() {
+        let mut s = Service::new("Test Service");
+
+        assert!(s.cgroup_path().is_none());
+
+        s.set_process_name("pidof");
+
+        assert_eq!(
+            "/sys/fs/cgroup/etsdiff.slice/pidof",
+            s.cgroup_path().unwrap()
+        );
+    }
+
     #[test]
     fn service_add_ports() {
         let mut s = Service::new("Test Service");
@@ -174,37 +220,51 @@ mod tests {
         assert_eq!(2, s.storage_paths.len());
     }
 
+    #[test]
+    fn service_storage_filter() {
+        let mut s = Service::new("Test Service");
+
+        assert!(s.storage_filter.is_empty());
+
+        assert!(s.add_storage_include("*.db").is_ok());
+        assert!(s.add_storage_exclude("*.tmp").is_ok());
+        assert!(s.add_storage_exclude("[").is_err());
+
+        assert_eq!(1, s.storage_filter.include.len());
+        assert_eq!(1, s.storage_filter.exclude.len());
+    }
+
     #[test]
     fn service_prepare() {
         let mut s = Service::new("Test Service");
 
-        assert!(!s.prepare().is_err());
+        assert!(s.prepare().is_ok());
 
         s.prepare = Some(SystemCall::new("ls"));
 
-        assert!(!s.prepare().is_err());
+        assert!(s.prepare().is_ok());
     }
 
     #[test]
     fn service_clean() {
         let mut s = Service::new("Test Service");
 
-        assert!(!s.clean().is_err());
+        assert!(s.clean().is_ok());
 
         s.clean = Some(SystemCall::new("ls -s"));
 
-        assert!(!s.clean().is_err());
+        assert!(s.clean().is_ok());
     }
 
     #[test]
     fn service_release() {
         let mut s = Service::new("Test Service");
 
-        assert!(!s.release().is_err());
+        assert!(s.release().is_ok());
 
         s.release = Some(SystemCall::new("ls -a"));
 
-        assert!(!s.release().is_err());
+        assert!(s.release().is_ok());
     }
 
     #[test]
@@ -216,12 +276,12 @@ mod tests {
         s.set_process_name("cargo"); // Assuming test with run with cargo
 
         s.prepare().unwrap();
-        assert_eq!(true, test_path.is_dir());
+        assert!(test_path.is_dir());
         if let Some(ref cg) = s.cgroup {
-            assert!(cg.tasks().len() > 0);
+            assert!(!cg.tasks().is_empty());
         }
 
         s.release().unwrap();
-        assert_eq!(false, test_path.is_dir());
+        assert!(!test_path.is_dir());
     }
 }
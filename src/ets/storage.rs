@@ -0,0 +1,360 @@
+// SPDX-FileCopyrightText: 2023 Davidson <twister@davidson.fr>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use glob::Pattern;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use super::report::StorageChanges;
+
+/// How the size of files is measured.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum StorageMode {
+    /// File lengths, as `ls -l` or `du --apparent-size`.
+    #[default]
+    Apparent,
+    /// Blocks allocated on disk, as `du`: sparse files count for the written
+    /// blocks only and hard links are counted once.
+    Allocated,
+}
+
+/// Glob patterns selecting files under storage paths, matched on the path
+/// relative to the storage path (`*` also matches `/`). An excluded directory
+/// is not walked. Without include pattern, all files are included.
+#[derive(Debug, Default, Clone)]
+pub struct StorageFilter {
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+}
+
+impl StorageFilter {
+    pub fn add_include(&mut self, pattern: &str) -> Result<(), Box<dyn Error>> {
+        self.include.push(Self::pattern(pattern)?);
+        Ok(())
+    }
+
+    pub fn add_exclude(&mut self, pattern: &str) -> Result<(), Box<dyn Error>> {
+        self.exclude.push(Self::pattern(pattern)?);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    fn pattern(pattern: &str) -> Result<Pattern, Box<dyn Error>> {
+        match Pattern::new(pattern) {
+            Ok(p) => Ok(p),
+            Err(err) => bail!("Invalid storage pattern {}: {}", pattern, err),
+        }
+    }
+
+    fn excludes(&self, relative: &Path) -> bool {
+        self.exclude.iter().any(|p| p.matches_path(relative))
+    }
+
+    fn includes(&self, relative: &Path) -> bool {
+        self.include.is_empty() || self.include.iter().any(|p| p.matches_path(relative))
+    }
+}
+
+// ===
+
+/// What is compared to know if a file was modified.
+#[derive(Debug, PartialEq, Clone)]
+struct FileState {
+    size: u64,
+    blocks: u64,
+    device: u64,
+    inode: u64,
+    mtime: (i64, i64),
+}
+
+impl FileState {
+    fn new(metadata: &Metadata) -> Self {
+        Self {
+            size: metadata.len(),
+            // st_blocks is always in 512 octets units
+            blocks: metadata.blocks() * 512,
+            device: metadata.dev(),
+            inode: metadata.ino(),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+        }
+    }
+}
+
+/// Files found under some paths at a given time. Directories are walked but
+/// not counted. Symlinks are counted as links (never followed, so no loop nor
+/// file outside of the paths), except the paths themselves that are resolved.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    files: HashMap<PathBuf, FileState>,
+}
+
+impl Snapshot {
+    /// Missing paths are taken as empty: a test can create them.
+    pub fn take(paths: &[String], filter: &StorageFilter) -> Result<Self, Box<dyn Error>> {
+        let mut snapshot = Self::default();
+        for path in paths {
+            match std::fs::canonicalize(path) {
+                Ok(path) => snapshot.walk(&path, &path, filter)?,
+                Err(err) if err.kind() == ErrorKind::NotFound => (),
+                Err(err) => bail!("Can't resolve {}: {}", path, err),
+            }
+        }
+        Ok(snapshot)
+    }
+
+    fn walk(
+        &mut self,
+        root: &Path,
+        path: &Path,
+        filter: &StorageFilter,
+    ) -> Result<(), Box<dyn Error>> {
+        // files can be removed while walking
+        let metadata = match std::fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => bail!("Can't read {}: {}", path.display(), err),
+        };
+        // a file given as storage path is matched on its name
+        let relative = if path == root {
+            path.file_name().map(Path::new).unwrap_or(path)
+        } else {
+            path.strip_prefix(root).unwrap_or(path)
+        };
+        if !(path == root && metadata.is_dir()) && filter.excludes(relative) {
+            return Ok(());
+        }
+        if metadata.is_dir() {
+            let entries = match std::fs::read_dir(path) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(err) => bail!("Can't read {}: {}", path.display(), err),
+            };
+            for entry in entries {
+                self.walk(root, &entry?.path(), filter)?;
+            }
+        } else if filter.includes(relative) {
+            self.files
+                .insert(path.to_path_buf(), FileState::new(&metadata));
+        }
+        Ok(())
+    }
+
+    /// Adds the files of another snapshot (files found in both are counted
+    /// once).
+    pub fn merge(&mut self, other: &Snapshot) {
+        for (path, state) in &other.files {
+            self.files.insert(path.clone(), state.clone());
+        }
+    }
+
+    /// Sorted paths of the files.
+    pub fn files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self
+            .files
+            .keys()
+            .map(|p| p.to_string_lossy().into())
+            .collect();
+        files.sort();
+        files
+    }
+
+    pub fn size(&self, mode: StorageMode) -> u64 {
+        match mode {
+            StorageMode::Apparent => self.files.values().map(|f| f.size).sum(),
+            StorageMode::Allocated => {
+                let mut inodes = HashSet::new();
+                self.files
+                    .values()
+                    .filter(|f| inodes.insert((f.device, f.inode)))
+                    .map(|f| f.blocks)
+                    .sum()
+            }
+        }
+    }
+
+    /// Changes from this snapshot to `after`, `delta` measured with `mode`.
+    pub fn changes(&self, after: &Snapshot, mode: StorageMode) -> StorageChanges {
+        let mut changes = StorageChanges {
+            delta: after.size(mode) as i64 - self.size(mode) as i64,
+            ..Default::default()
+        };
+        for (path, state) in &after.files {
+            match self.files.get(path) {
+                None => changes.created += 1,
+                Some(before) if before != state => changes.modified += 1,
+                Some(_) => (),
+            }
+        }
+        changes.deleted = self
+            .files
+            .keys()
+            .filter(|path| !after.files.contains_key(*path))
+            .count() as u64;
+        changes
+    }
+}
+
+/// Size given by a storage probe: the first number of its output.
+pub fn probe_octets(output: &str) -> Result<u64, Box<dyn Error>> {
+    match output
+        .split_whitespace()
+        .find_map(|w| w.parse::<u64>().ok())
+    {
+        Some(octets) => Ok(octets),
+        None => bail!("No size in storage probe output: {}", output.trim()),
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_changes() -> Result<(), Box<dyn Error>> {
+        let root = "/tmp/etsdiff_snapshot";
+        let _ = std::fs::remove_dir_all(root);
+        std::fs::create_dir_all(format!("{root}/dir"))?;
+        std::fs::write(format!("{root}/kept"), "0123456789")?;
+        std::fs::write(format!("{root}/modified"), "0123")?;
+        std::fs::write(format!("{root}/dir/deleted"), "01234")?;
+
+        let paths = vec![root.to_string(), format!("{root}/missing")];
+        let before = Snapshot::take(&paths, &StorageFilter::default())?;
+        assert_eq!(19, before.size(StorageMode::Apparent));
+
+        std::fs::write(format!("{root}/modified"), "01234567")?;
+        std::fs::remove_file(format!("{root}/dir/deleted"))?;
+        std::fs::write(format!("{root}/missing"), "01")?;
+        std::fs::write(format!("{root}/dir/created"), "012")?;
+
+        let after = Snapshot::take(&paths, &StorageFilter::default())?;
+        assert_eq!(23, after.size(StorageMode::Apparent));
+
+        let changes = before.changes(&after, StorageMode::Apparent);
+        assert_eq!(
+            StorageChanges {
+                delta: 4,
+                created: 2,
+                modified: 1,
+                deleted: 1
+            },
+            changes
+        );
+
+        std::fs::remove_dir_all(root)?;
+        assert_eq!(
+            0,
+            Snapshot::take(&paths, &StorageFilter::default())?.size(StorageMode::Apparent)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_filter() -> Result<(), Box<dyn Error>> {
+        let root = "/tmp/etsdiff_filter";
+        let _ = std::fs::remove_dir_all(root);
+        std::fs::create_dir_all(format!("{root}/data/cache"))?;
+        std::fs::write(format!("{root}/data/table.db"), "0123456789")?;
+        std::fs::write(format!("{root}/data/table.tmp"), "012")?;
+        std::fs::write(format!("{root}/data/cache/page.db"), "01234")?;
+        std::fs::write(format!("{root}/lock"), "0")?;
+        std::fs::write(format!("{root}/server.log"), "01")?;
+
+        let paths = vec![root.to_string()];
+        let take = |include: &[&str], exclude: &[&str]| -> Result<Vec<String>, Box<dyn Error>> {
+            let mut filter = StorageFilter::default();
+            for p in include {
+                filter.add_include(p)?;
+            }
+            for p in exclude {
+                filter.add_exclude(p)?;
+            }
+            Ok(Snapshot::take(&paths, &filter)?
+                .files()
+                .iter()
+                .map(|f| f.strip_prefix(root).unwrap().into())
+                .collect())
+        };
+
+        assert_eq!(5, take(&[], &[])?.len());
+        assert_eq!(
+            vec!["/data/cache/page.db", "/data/table.db"],
+            take(&["*.db"], &[])?
+        );
+        assert_eq!(
+            vec!["/data/table.db", "/lock"],
+            take(&[], &["*.tmp", "*.log", "data/cache"])?
+        );
+        assert_eq!(
+            vec!["/data/table.db"],
+            take(&["data/*"], &["*/cache/*", "*.tmp"])?
+        );
+
+        // file given as storage path
+        let file = vec![format!("{root}/server.log")];
+        let mut filter = StorageFilter::default();
+        filter.add_exclude("*.log")?;
+        assert_eq!(0, Snapshot::take(&file, &filter)?.files().len());
+
+        assert!(filter.add_include("[").is_err());
+
+        std::fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn storage_probe_octets() {
+        assert_eq!(42, probe_octets("42\n").unwrap());
+        assert_eq!(4096, probe_octets("4096\t/var/lib/db\n").unwrap());
+        assert_eq!(
+            8192,
+            probe_octets(" pg_size\n---------\n    8192\n").unwrap()
+        );
+        assert!(probe_octets("").is_err());
+        assert!(probe_octets("no size").is_err());
+    }
+
+    #[test]
+    fn snapshot_allocated() -> Result<(), Box<dyn Error>> {
+        let root = "/tmp/etsdiff_allocated";
+        let _ = std::fs::remove_dir_all(root);
+        std::fs::create_dir_all(format!("{root}/data"))?;
+
+        // sparse file: 1 Mio long, nothing written
+        let sparse = std::fs::File::create(format!("{root}/data/sparse"))?;
+        sparse.set_len(1024 * 1024)?;
+        std::fs::write(format!("{root}/data/file"), "0123456789")?;
+        std::fs::hard_link(format!("{root}/data/file"), format!("{root}/data/link"))?;
+        std::os::unix::fs::symlink(root, format!("{root}/data/loop"))?;
+        std::os::unix::fs::symlink(format!("{root}/data"), format!("{root}/resolved"))?;
+
+        let snapshot = Snapshot::take(&[format!("{root}/resolved")], &StorageFilter::default())?;
+        let file = std::fs::metadata(format!("{root}/data/file"))?;
+        let symlink = std::fs::symlink_metadata(format!("{root}/data/loop"))?;
+
+        assert_eq!(
+            1024 * 1024 + 10 + 10 + symlink.len(),
+            snapshot.size(StorageMode::Apparent)
+        );
+        assert!(file.blocks() > 0);
+        assert_eq!(
+            (file.blocks() + symlink.blocks()) * 512,
+            snapshot.size(StorageMode::Allocated)
+        );
+
+        std::fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+}
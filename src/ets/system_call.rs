@@ -26,10 +26,7 @@ impl SystemCall {
     pub fn path(&self) -> String {
         let path = self.command.get_program().to_str().map(|s| s.to_string());
 
-        match path {
-            Some(path) => path,
-            None => String::new(),
-        }
+        path.unwrap_or_default()
     }
 
     pub fn arguments(&self) -> Vec<String> {
@@ -53,6 +50,17 @@ impl SystemCall {
 
         Ok(())
     }
+
+    /// Executes the command and returns its standard output.
+    pub fn output(&mut self) -> Result<String, Box<dyn Error>> {
+        let output = self.command.stdout(Stdio::piped()).output()?;
+
+        if !output.status.success() {
+            bail!("SystemCall.output() return error");
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into())
+    }
 }
 
 // -----------------------------------------------------------------------------
@@ -83,6 +91,15 @@ mod tests {
     #[test]
     fn execute_with_success() {
         let mut sc = SystemCall::new("ls -l");
-        assert!(!sc.execute().is_err());
+        assert!(sc.execute().is_ok());
+    }
+
+    #[test]
+    fn output_with_success() {
+        let mut sc = SystemCall::new("echo 42 octets");
+        assert_eq!("42 octets\n", sc.output().unwrap());
+
+        sc = SystemCall::new("ls /unknowpath/unknowcommand");
+        assert!(sc.output().is_err());
     }
 }
@@ -9,6 +9,15 @@ pub trait Test {
     fn name(&self) -> &String;
     fn services_names(&self) -> &Vec<String>;
     fn run(&mut self) -> Result<(), Box<dyn Error>>;
+    /// Number of work units (requests, transactions...) done by a run, to
+    /// report values per unit.
+    fn functional_units(&self) -> Option<u64> {
+        None
+    }
+    /// Number of warm-up runs, overriding the campaign one.
+    fn warmup(&self) -> Option<u32> {
+        None
+    }
 }
 
 // ===
@@ -17,6 +26,8 @@ pub struct SystemCallTest {
     name: String,
     services_names: Vec<String>,
     system_call: SystemCall,
+    functional_units: Option<u64>,
+    warmup: Option<u32>,
 }
 
 impl SystemCallTest {
@@ -25,9 +36,19 @@ impl SystemCallTest {
             name: name.into(),
             services_names: vec![],
             system_call: SystemCall::new(commandline),
+            functional_units: None,
+            warmup: None,
         }
     }
 
+    pub fn set_functional_units(&mut self, functional_units: u64) {
+        self.functional_units = Some(functional_units);
+    }
+
+    pub fn set_warmup(&mut self, warmup: u32) {
+        self.warmup = Some(warmup);
+    }
+
     pub fn add_service_name(&mut self, service_name: &str) {
         self.services_names.push(service_name.into());
     }
@@ -47,6 +68,14 @@ impl Test for SystemCallTest {
 
         Ok(())
     }
+
+    fn functional_units(&self) -> Option<u64> {
+        self.functional_units
+    }
+
+    fn warmup(&self) -> Option<u32> {
+        self.warmup
+    }
 }
 
 // -----------------------------------------------------------------------------
@@ -73,21 +102,27 @@ mod tests {
         assert_eq!(sct.services_names.len(), 1);
         sct.add_service_name("sn2");
         assert_eq!(sct.services_names.len(), 2);
-        let mut i = 0;
-        for sn in sct.services_names.iter() {
+        for (i, sn) in sct.services_names.iter().enumerate() {
             match i {
                 0 => assert_eq!(sn, "sn1"),
                 1 => assert_eq!(sn, "sn2"),
                 _ => panic!("Uncovered service name"),
             }
-            i += 1;
         }
     }
 
+    #[test]
+    fn system_call_test_functional_units() {
+        let mut sct = SystemCallTest::new("TestName", "ls");
+        assert!(sct.functional_units().is_none());
+        sct.set_functional_units(1000);
+        assert_eq!(Some(1000), sct.functional_units());
+    }
+
     #[test]
     fn system_call_test_run() {
         let mut sct = SystemCallTest::new("TestName", "ls");
-        assert!(!sct.run().is_err());
+        assert!(sct.run().is_ok());
     }
 
     #[test]
@@ -95,7 +130,7 @@ mod tests {
         let now = Instant::now();
         let mut sct = SystemCallTest::new("TestName", "sleep 5");
 
-        assert!(!sct.run().is_err());
+        assert!(sct.run().is_ok());
         assert!(now.elapsed().as_secs() >= 5);
     }
 }
@@ -12,6 +12,8 @@ extern crate simple_error;
 extern crate rouille;
 
 use clap::{arg, value_parser, Command};
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs::File;
 use std::path::{Path, PathBuf};
@@ -20,12 +22,22 @@ pub mod ets;
 
 use crate::ets::config_reader::{ConfigReader, TOMLConfigReader};
 use crate::ets::etsdiff::ETSdiff;
+use crate::ets::packet_capture::{parse_host, pcap_transfer, CaptureRule};
+use crate::ets::report::ServiceTransfer;
+
+#[derive(Serialize)]
+struct TransferSummary {
+    transfer: u64,
+    transfer_by_port: BTreeMap<u32, ServiceTransfer>,
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("ETSDiff")
         .version("0.1")
         .author("Twister <twister@davidson.fr>")
         .about("Comparing programs with 3 criterias: Energy, Transfer and Storage")
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
         .arg(
             arg!([config] "TOML config file")
                 .required(true)
@@ -36,8 +48,76 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .required(false)
                 .value_parser(value_parser!(String)),
         )
+        .subcommand(
+            Command::new("transfer")
+                .about("Recompute transfer from an archived pcap or pcapng capture")
+                .arg(
+                    arg!(<pcap> "Capture file")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(-p --port <PORT> "Service port (repeatable)")
+                        .required(true)
+                        .multiple_occurrences(true)
+                        .value_parser(value_parser!(u32)),
+                )
+                .arg(
+                    arg!(--host <HOST> "Services host or network (repeatable)")
+                        .required(false)
+                        .multiple_occurrences(true)
+                        .default_value("127.0.0.1")
+                        .value_parser(value_parser!(String)),
+                ),
+        )
         .get_matches();
 
+    if let Some(("transfer", sub)) = matches.subcommand() {
+        let pcap = sub.get_one::<String>("pcap").unwrap();
+        let ports: Vec<u32> = sub.get_many::<u32>("port").unwrap().copied().collect();
+        let mut hosts = Vec::new();
+        for host in sub.get_many::<String>("host").unwrap() {
+            match parse_host(host) {
+                Ok(host) => hosts.push(host),
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        let rule = CaptureRule {
+            interface: None,
+            hosts,
+            ports: ports.clone(),
+            filter: None,
+        };
+
+        let counter = match pcap_transfer(pcap, &[rule]) {
+            Ok(counter) => counter,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        };
+        let summary = TransferSummary {
+            transfer: counter.total,
+            transfer_by_port: ports
+                .iter()
+                .map(|p| {
+                    (
+                        *p,
+                        ServiceTransfer {
+                            ingress: counter.ingress(&[*p]),
+                            egress: counter.egress(&[*p]),
+                        },
+                    )
+                })
+                .collect(),
+        };
+        print!("{}", serde_yaml::to_string(&summary)?);
+        return Ok(());
+    }
+
     if let Some(config) = matches.get_one::<PathBuf>("config") {
         if !config.is_file() {
             eprintln!("Error: config file \"{}\" not found", config.display());
@@ -51,7 +131,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let config = std::fs::read_to_string(config).expect("could not read config file");
 
         let mut etsd = ETSdiff::new();
-        TOMLConfigReader::read(&config, &mut etsd);
+        if let Err(e) = TOMLConfigReader::read(&config, &mut etsd) {
+            eprintln!("Error in config file: {e}");
+            std::process::exit(1);
+        }
 
         if let Err(e) = etsd.execute() {
             eprintln!("Error while executing etsdiff...");